@@ -1,48 +1,232 @@
-use sdl2::pixels::Color;
+use rand::Rng;
 
+use crate::display::Color;
+use crate::fov;
 use crate::items::Inventory;
+use crate::map;
+use crate::dijkstra::DijkstraMap;
+use super::{Map, NPCTable};
+
+// Everyone gains their speed in energy each turn and spends ACTION_COST
+// whenever they do something, so a creature with NORMAL_SPEED acts once a
+// turn, a faster one gets the occasional extra action and a slower one
+// sometimes has to skip a turn.
+pub const ACTION_COST: i32 = 12;
+pub const NORMAL_SPEED: i32 = 12;
 
 pub struct Player {
 	pub name: String,
-	ac: u8,
-	hp: u8,
+	pub ac: u8,
+	pub hp: u8,
 	pub row: usize,
 	pub col: usize,
 	pub inventory: Inventory,
+	pub speed: i32,
+	pub energy: i32,
 }
 
 impl Player {
 	pub fn new(name: String) -> Player {
-		Player { name, ac: 10, hp: 10, row:0, col:0, inventory: Inventory::new() }
+		Player { name, ac: 10, hp: 10, row:0, col:0, inventory: Inventory::new(),
+			speed: NORMAL_SPEED, energy: ACTION_COST }
 	}
 }
 
+// What an NPC decided to do with its turn. The NPC is borrowed while it's
+// making up its mind, so anything that touches the NPCTable happens after.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+	Wait,
+	Move(usize, usize),
+	AttackPlayer,
+}
+
 pub trait Act {
-	fn act(&mut self, state: &mut super::GameState);
+	fn act(&mut self, state: &mut super::GameState, map: &Map, npcs: &NPCTable) -> Action;
 	fn get_tile_info(&self) -> (Color, char);
+	fn get_name(&self) -> String;
+	fn get_ac(&self) -> u8;
+	fn get_hp(&self) -> u8;
+	fn get_max_hp(&self) -> u8;
+	fn set_hp(&mut self, hp: u8);
+	// The most damage the NPC can do with one hit
+	fn get_dmg(&self) -> u8;
+	fn get_loc(&self) -> (usize, usize);
+	fn set_loc(&mut self, row: usize, col: usize);
+	fn get_speed(&self) -> i32;
+	fn get_energy(&self) -> i32;
+	fn set_energy(&mut self, energy: i32);
 }
 
 pub struct Monster {
+	name: String,
 	ac: u8,
 	hp: u8,
+	max_hp: u8,
+	dmg: u8,
 	symbol: char,
 	row: usize,
 	col: usize,
 	color: Color,
+	speed: i32,
+	energy: i32,
 }
 
 impl Monster {
-	pub fn new(ac:u8, hp: u8, symbol: char, row: usize, col: usize, color: Color) -> Monster {
-		Monster { ac, hp, symbol, row, col, color }
+	pub fn new(name: &str, ac:u8, hp: u8, dmg: u8, symbol: char, (row, col): (usize, usize),
+			color: Color) -> Monster {
+		Monster { name: String::from(name), ac, hp, max_hp: hp, dmg, symbol, row, col, color,
+			speed: NORMAL_SPEED, energy: 0 }
+	}
+
+	pub fn set_speed(&mut self, speed: i32) {
+		self.speed = speed;
+	}
+
+	fn can_see_player(&self, state: &super::GameState, map: &Map) -> bool {
+		let visible = fov::visible_squares(map, self.row, self.col, super::FOV_RADIUS);
+		visible.contains(&(state.player.row, state.player.col))
+	}
+
+	// Badly hurt monsters try to get away
+	fn is_fleeing(&self) -> bool {
+		self.hp as u32 * 4 <= self.max_hp as u32
+	}
+
+	// One step downhill on the given Dijkstra map, if there's anywhere
+	// downhill to go that isn't already taken
+	fn step_downhill(&self, dm: &Option<DijkstraMap>, state: &super::GameState, map: &Map,
+			npcs: &NPCTable) -> Option<(usize, usize)> {
+		let can_step = |r: usize, c: usize| can_enter(map, npcs, state, r as i32, c as i32);
+		dm.as_ref()?.downhill(self.row, self.col, &can_step)
+	}
+
+	fn wander(&self, state: &mut super::GameState, map: &Map, npcs: &NPCTable) -> Action {
+		let mut options = Vec::new();
+		for r in -1..2 {
+			for c in -1..2 {
+				if r == 0 && c == 0 { continue; }
+
+				let nr = self.row as i32 + r;
+				let nc = self.col as i32 + c;
+				if can_enter(map, npcs, state, nr, nc) {
+					options.push((nr as usize, nc as usize));
+				}
+			}
+		}
+
+		if options.is_empty() {
+			Action::Wait
+		} else {
+			let j = state.rng.gen_range(0, options.len());
+			Action::Move(options[j].0, options[j].1)
+		}
 	}
 }
 
 impl Act for Monster {
-	fn act(&mut self, state: &mut super::GameState) {
-		println!("My location is ({}, {})", self.row, self.col);
+	// Monsters that can see the player head straight for them (and take a
+	// swing once they're next to them), unless they're too hurt to fight in
+	// which case they run for it. Otherwise they just wander around.
+	fn act(&mut self, state: &mut super::GameState, map: &Map, npcs: &NPCTable) -> Action {
+		let dr = (self.row as i32 - state.player.row as i32).abs();
+		let dc = (self.col as i32 - state.player.col as i32).abs();
+		let adjacent = dr <= 1 && dc <= 1;
+
+		if self.is_fleeing() && self.can_see_player(state, map) {
+			if let Some(step) = self.step_downhill(&state.from_player, state, map, npcs) {
+				return Action::Move(step.0, step.1);
+			}
+		}
+
+		// Including when they've been cornered
+		if adjacent {
+			return Action::AttackPlayer;
+		}
+
+		if !self.is_fleeing() && self.can_see_player(state, map) {
+			if let Some(step) = self.step_downhill(&state.to_player, state, map, npcs) {
+				return Action::Move(step.0, step.1);
+			}
+		}
+
+		self.wander(state, map, npcs)
 	}
 
 	fn get_tile_info(&self) -> (Color, char) {
 		(self.color, self.symbol)
 	}
+
+	fn get_name(&self) -> String {
+		self.name.clone()
+	}
+
+	fn get_ac(&self) -> u8 {
+		self.ac
+	}
+
+	fn get_hp(&self) -> u8 {
+		self.hp
+	}
+
+	fn get_max_hp(&self) -> u8 {
+		self.max_hp
+	}
+
+	fn set_hp(&mut self, hp: u8) {
+		self.hp = hp;
+	}
+
+	fn get_dmg(&self) -> u8 {
+		self.dmg
+	}
+
+	fn get_loc(&self) -> (usize, usize) {
+		(self.row, self.col)
+	}
+
+	fn set_loc(&mut self, row: usize, col: usize) {
+		self.row = row;
+		self.col = col;
+	}
+
+	fn get_speed(&self) -> i32 {
+		self.speed
+	}
+
+	fn get_energy(&self) -> i32 {
+		self.energy
+	}
+
+	fn set_energy(&mut self, energy: i32) {
+		self.energy = energy;
+	}
+}
+
+// Is the square somewhere an NPC could step into? It has to be on the map,
+// passable and not already occupied by the player or another NPC.
+pub fn can_enter(map: &Map, npcs: &NPCTable, state: &super::GameState, r: i32, c: i32) -> bool {
+	if !map.in_bounds(r, c) {
+		return false;
+	}
+
+	let loc = (r as usize, c as usize);
+	map::is_passable(map[loc]) && !npcs.contains_key(&loc)
+		&& loc != (state.player.row, state.player.col)
+}
+
+// Move the NPC at from to to, updating both its key in the NPCTable and its
+// own record of where it is so the two can't drift apart. Returns false (and
+// leaves everything alone) if there's no NPC at from or it can't enter to.
+pub fn move_npc(map: &Map, npcs: &mut NPCTable, state: &super::GameState,
+		from: (usize, usize), to: (usize, usize)) -> bool {
+	if !npcs.contains_key(&from) || !can_enter(map, npcs, state, to.0 as i32, to.1 as i32) {
+		return false;
+	}
+
+	let npc = npcs.remove(&from).unwrap();
+	npc.borrow_mut().set_loc(to.0, to.1);
+	npcs.insert(to, npc);
+
+	true
 }
@@ -0,0 +1,62 @@
+use rand::Rng;
+
+use crate::actor::Act;
+use super::{GameRng, GameState, NPCTable};
+
+// Everyone gets a small bonus to their d20 roll to hit, otherwise an ac of
+// 13 is a bit too hard to hit
+const TO_HIT_BONUS: u8 = 3;
+const UNARMED_DMG: u8 = 2;
+
+fn attack_hits(ac: u8, rng: &mut GameRng) -> bool {
+	let roll: u8 = rng.gen_range(1, 21);
+	roll + TO_HIT_BONUS >= ac
+}
+
+fn roll_dmg(max: u8, rng: &mut GameRng) -> u8 {
+	rng.gen_range(1, max.max(1) + 1)
+}
+
+// The player swings at the NPC at loc with whatever they have in hand. If
+// the NPC is killed, it's removed from the NPCTable.
+pub fn player_attacks(state: &mut GameState, npcs: &mut NPCTable, loc: (usize, usize)) {
+	let npc = npcs.get(&loc).unwrap().clone();
+	let name = npc.borrow().get_name();
+
+	if !attack_hits(npc.borrow().get_ac(), &mut state.rng) {
+		state.write_msg_buff(&format!("You miss the {}.", name));
+		return;
+	}
+
+	let max_dmg = match state.player.inventory.get_wielded() {
+		Some(weapon) => weapon.dmg,
+		None => UNARMED_DMG,
+	};
+	let dmg = roll_dmg(max_dmg, &mut state.rng);
+	let hp = npc.borrow().get_hp().saturating_sub(dmg);
+	npc.borrow_mut().set_hp(hp);
+
+	if hp == 0 {
+		state.write_msg_buff(&format!("You kill the {}!", name));
+		npcs.remove(&loc);
+	} else {
+		state.write_msg_buff(&format!("You hit the {}.", name));
+	}
+}
+
+pub fn npc_attacks(state: &mut GameState, npc: &dyn Act) {
+	let name = npc.get_name();
+
+	if attack_hits(state.player.ac, &mut state.rng) {
+		let dmg = roll_dmg(npc.get_dmg(), &mut state.rng);
+		state.player.hp = state.player.hp.saturating_sub(dmg);
+		state.write_msg_buff(&format!("The {} hits you!", name));
+
+		if state.player.hp == 0 {
+			state.write_msg_buff("You die...");
+			state.killed_by = Some(name);
+		}
+	} else {
+		state.write_msg_buff(&format!("The {} misses you.", name));
+	}
+}
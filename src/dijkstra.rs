@@ -0,0 +1,270 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::Map;
+
+// Brogue's trick for making fleeing monsters smarter than just running
+// directly away: scale the distances by a bit more than -1 and then let
+// them settle again. Squares that lead somewhere open end up lower than
+// dead ends, so monsters don't flee into corners. (6/5 since I'm sticking
+// to integers.)
+const FLEE_NUM: i32 = -6;
+const FLEE_DEN: i32 = 5;
+
+// A Dijkstra map (aka a flow field): every square on the map holds the cost
+// of the cheapest route from it to the nearest goal. Anything wanting to
+// reach a goal just steps to whichever neighbouring square is lowest, so a
+// single map can steer any number of monsters without each one needing a
+// path of its own.
+#[derive(Debug, Clone)]
+pub struct DijkstraMap {
+	values: Vec<Vec<Option<i32>>>,
+}
+
+impl DijkstraMap {
+	// Distances to the nearest of the goals. cost(r, c) is what it costs to
+	// step into a square, or None if it can't be entered, same as for
+	// pathfinding::find_path(). Squares with no route to any goal have no
+	// value.
+	pub fn new(map: &Map, goals: &[(usize, usize)], cost: &dyn Fn(usize, usize) -> Option<usize>) -> DijkstraMap {
+		let mut dm = DijkstraMap { values: vec![vec![None; map.width()]; map.height()] };
+		for goal in goals {
+			dm.values[goal.0][goal.1] = Some(0);
+		}
+		dm.settle(map, cost);
+
+		dm
+	}
+
+	// A map for getting away from the goals of this one. Every square is
+	// lower the further (and more usefully) away from the goals it is.
+	pub fn flee(&self, map: &Map, cost: &dyn Fn(usize, usize) -> Option<usize>) -> DijkstraMap {
+		let values = self.values.iter()
+			.map(|row| row.iter().map(|v| v.map(|d| d * FLEE_NUM / FLEE_DEN)).collect())
+			.collect();
+		let mut dm = DijkstraMap { values };
+		dm.settle(map, cost);
+
+		dm
+	}
+
+	pub fn get(&self, r: usize, c: usize) -> Option<i32> {
+		self.values[r][c]
+	}
+
+	// The neighbouring square with the lowest value, if any are lower than
+	// where we're standing. can_step() lets the caller rule out squares (say,
+	// because someone else is already there), in which case the next best
+	// square downhill is used.
+	pub fn downhill(&self, r: usize, c: usize, can_step: &dyn Fn(usize, usize) -> bool) -> Option<(usize, usize)> {
+		let mut best = None;
+		let mut lowest = self.values[r][c]?;
+
+		for dr in -1..2 {
+			for dc in -1..2 {
+				if dr == 0 && dc == 0 { continue; }
+
+				let nr = r as i32 + dr;
+				let nc = c as i32 + dc;
+				if nr < 0 || nc < 0 || nr as usize >= self.values.len()
+						|| nc as usize >= self.values[0].len() {
+					continue;
+				}
+
+				let loc = (nr as usize, nc as usize);
+				if let Some(v) = self.values[loc.0][loc.1] {
+					if v < lowest && can_step(loc.0, loc.1) {
+						lowest = v;
+						best = Some(loc);
+					}
+				}
+			}
+		}
+
+		best
+	}
+
+	// Dijkstra's algorithm, except every square that already has a value is
+	// a starting point. Values only ever go down: a square ends up with the
+	// lowest of what it started with and what it costs to get from there to
+	// a neighbour plus that neighbour's value.
+	fn settle(&mut self, map: &Map, cost: &dyn Fn(usize, usize) -> Option<usize>) {
+		let mut queue = BinaryHeap::new();
+		for r in 0..self.values.len() {
+			for c in 0..self.values[r].len() {
+				if let Some(v) = self.values[r][c] {
+					queue.push(Reverse((v, (r, c))));
+				}
+			}
+		}
+
+		while let Some(Reverse((v, loc))) = queue.pop() {
+			if self.values[loc.0][loc.1] != Some(v) {
+				// Stale entry, a lower value was found after this was queued
+				continue;
+			}
+
+			// Stepping from a neighbour into loc costs whatever loc costs
+			let step = match cost(loc.0, loc.1) {
+				Some(s) => s.max(1) as i32,
+				None => continue,
+			};
+
+			for dr in -1..2 {
+				for dc in -1..2 {
+					if dr == 0 && dc == 0 { continue; }
+
+					let nr = loc.0 as i32 + dr;
+					let nc = loc.1 as i32 + dc;
+					if !map.in_bounds(nr, nc) {
+						continue;
+					}
+
+					let next = (nr as usize, nc as usize);
+					if cost(next.0, next.1).is_none() {
+						continue;
+					}
+
+					let nv = v + step;
+					let better = match self.values[next.0][next.1] {
+						Some(old) => nv < old,
+						None => true,
+					};
+					if better {
+						self.values[next.0][next.1] = Some(nv);
+						queue.push(Reverse((nv, next)));
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::map;
+	use crate::pathfinding;
+	use crate::GameRng;
+	use rand::SeedableRng;
+
+	fn open_map() -> Map {
+		Map::new(10, 10, map::Tile::Grass)
+	}
+
+	#[test]
+	fn distances_from_one_goal() {
+		let map = open_map();
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let dm = DijkstraMap::new(&map, &[(5, 5)], &cost);
+
+		assert_eq!(dm.get(5, 5), Some(0));
+		assert_eq!(dm.get(4, 4), Some(1));
+		assert_eq!(dm.get(0, 9), Some(5));
+		assert_eq!(dm.get(9, 0), Some(5));
+	}
+
+	#[test]
+	fn distances_to_nearest_goal() {
+		let map = open_map();
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let dm = DijkstraMap::new(&map, &[(0, 0), (9, 9)], &cost);
+
+		assert_eq!(dm.get(1, 1), Some(1));
+		assert_eq!(dm.get(8, 8), Some(1));
+		assert_eq!(dm.get(4, 4), Some(4));
+		assert_eq!(dm.get(5, 5), Some(4));
+	}
+
+	#[test]
+	fn matches_find_path_costs() {
+		let map = map::generate_island(65, &mut GameRng::seed_from_u64(65));
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		// The passable square closest to the middle of the island
+		let goal = map.coords()
+			.filter(|&(r, c)| cost(r, c).is_some())
+			.min_by_key(|&(r, c)| (r as i32 - 32).abs() + (c as i32 - 32).abs())
+			.unwrap();
+		let dm = DijkstraMap::new(&map, &[goal], &cost);
+
+		let mut checked = 0;
+		for r in (0..65).step_by(4) {
+			for c in (0..65).step_by(4) {
+				if cost(r, c).is_none() {
+					continue;
+				}
+
+				let found = pathfinding::find_path(&map, &cost, r, c, goal.0, goal.1)
+					.map(|(_, total)| total as i32);
+				assert_eq!(dm.get(r, c), found, "from {:?}", (r, c));
+				checked += 1;
+			}
+		}
+		assert!(checked > 0);
+	}
+
+	#[test]
+	fn walls_and_unreachable_squares() {
+		let mut map = open_map();
+		for r in 0..10 {
+			map[(r, 5)] = map::Tile::Wall;
+		}
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let dm = DijkstraMap::new(&map, &[(0, 0)], &cost);
+
+		assert_eq!(dm.get(0, 4), Some(4));
+		assert_eq!(dm.get(0, 5), None);
+		assert_eq!(dm.get(0, 6), None);
+	}
+
+	#[test]
+	fn rolling_downhill_reaches_the_goal() {
+		let mut map = open_map();
+		for r in 0..8 {
+			map[(r, 5)] = map::Tile::Wall;
+		}
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let dm = DijkstraMap::new(&map, &[(0, 9)], &cost);
+
+		let mut loc = (0, 0);
+		let mut steps = 0;
+		while let Some(next) = dm.downhill(loc.0, loc.1, &|_, _| true) {
+			loc = next;
+			steps += 1;
+		}
+
+		assert_eq!(loc, (0, 9));
+		assert_eq!(steps, 16);
+	}
+
+	#[test]
+	fn downhill_skips_squares_it_cant_step_into() {
+		let map = open_map();
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let dm = DijkstraMap::new(&map, &[(5, 9)], &cost);
+
+		// (4, 6), (5, 6) and (6, 6) are all a step closer
+		let next = dm.downhill(5, 5, &|_, _| true).unwrap();
+		assert_eq!(dm.get(next.0, next.1), Some(3));
+		let next = dm.downhill(5, 5, &|r, c| c != 6 || r == 6).unwrap();
+		assert_eq!(next, (6, 6));
+		assert_eq!(dm.downhill(5, 5, &|_, _| false), None);
+		assert_eq!(dm.downhill(5, 9, &|_, _| true), None);
+	}
+
+	#[test]
+	fn fleeing_heads_away() {
+		let map = open_map();
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let dm = DijkstraMap::new(&map, &[(5, 5)], &cost);
+		let flee = dm.flee(&map, &cost);
+
+		let mut loc = (4, 4);
+		for _ in 0..4 {
+			let next = flee.downhill(loc.0, loc.1, &|_, _| true).unwrap();
+			assert!(dm.get(next.0, next.1) > dm.get(loc.0, loc.1));
+			loc = next;
+		}
+		assert_eq!(loc, (0, 0));
+	}
+}
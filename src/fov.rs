@@ -1,6 +1,10 @@
+use std::collections::HashSet;
+
+use crate::display;
 use crate::map;
 use super::{Map, NPCTable};
 use crate::items::{ItemsTable, TileInfo};
+use crate::world::Level;
 
 fn calc_actual_tile(r: usize, c: usize, map: &Map, 
 		npcs: &NPCTable, items: &ItemsTable) -> map::Tile {
@@ -13,130 +17,174 @@ fn calc_actual_tile(r: usize, c: usize, map: &Map,
 		let ti = m.get_tile_info();
 		map::Tile::Thing(ti.0, ti.1)
 	} else {
-		map[r][c]
+		map[(r, c)]
 	}
 }
 
-// Using bresenham line casting to detect blocked squares. If a ray hits
-// a Wall before reaching target then we can't see it. Bresenham isn't 
-// really a good way to do this because it leaves blindspots the further
-// away you get and also is rather ineffecient (you visit the same squares 
-// several times). My original plan, after making a prototype with beamcasting,
-// was to switch to shadowcasting. But bresenham seemed sufficiently fast
-// and I haven't seen and blindspots (perhaps because I'm keeping the FOV at
-// 40x20).
-//
-// As well, I wanted to have the trees obscure/reduce the FOV instead of outright
-// blocking vision and I couldn't think of a simple way to do that with 
-// shadowcasting.
-fn mark_visible(r1: i32, c1: i32, r2: i32, c2: i32, map: &Map,
-		npcs: &NPCTable, items: &ItemsTable,
-		v_matrix: &mut Vec<Vec<map::Tile>>) {
-	let mut r = r1;
-	let mut c = c1;
-	let mut error = 0;
-
-	let mut r_step = 1;
-	let mut delta_r = r2 - r;
-	if delta_r < 0 {
-		delta_r = -delta_r;
-		r_step = -1;
-	} 
-
-	let mut c_step = 1;
-	let mut delta_c = c2 - c;
-	if delta_c < 0 {
-		delta_c = -delta_c;
-		c_step = -1;
-	} 
-
-	let mut r_end = r2;
-	let mut c_end = c2;
-	if delta_c <= delta_r {
-		let criterion = delta_r / 2;
-		loop {
-			if r_step > 0 && r >= r_end + r_step {
-				break;
-			} else if r_step < 0 && r <= r_end + r_step {
-				break;
-			}
+fn calc_remembered_tile(r: usize, c: usize, map: &Map, items: &ItemsTable) -> map::Tile {
+	if items.count_at(r, c) > 0 {
+		let ti = items.peek_top(r, c).get_tile_info();
+		map::Tile::Thing(ti.0, ti.1)
+	} else {
+		map[(r, c)]
+	}
+}
 
-			if !map::in_bounds(map, r, c) {
-				return;
-			}
+// Recursive symmetric shadowcasting, more or less straight from Albert Ford's
+// write up (https://www.albertford.com/shadowcasting/). I'd originally
+// stuck with Bresenham rays because I couldn't see how to have trees reduce
+// visibility instead of blocking it outright, but it falls out of the
+// recursion fine: the part of the next row that's behind a run of trees is
+// scanned with a shorter range than the part that's behind open ground.
+const TREE_PENALTY: i32 = 3;
 
-			let vm_r = (r - r1 + 10) as usize;
-			let vm_c = (c - c1 + 20) as usize;
-			v_matrix[vm_r][vm_c] = calc_actual_tile(r as usize, c as usize, map, npcs, items);
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Sight {
+	Clear,
+	Tree,
+	Blocked,
+}
 
-			if !map::is_clear(map[r as usize][c as usize]) {
-				return;
-			}
+impl Sight {
+	fn penalty(self) -> i32 {
+		match self {
+			Sight::Tree => TREE_PENALTY,
+			_ => 0,
+		}
+	}
+}
 
-			// I want trees to not totally block light, but instead reduce visibility
-			if map::Tile::Tree == map[r as usize][c as usize] && !(r == r1 && c == c1) {
-				if r_step > 0 {
-					r_end -= 3;
-				} else {
-					r_end += 3;
-				}
-			}
+// Slopes are kept as fractions so that the symmetry check is exact
+#[derive(Debug, Copy, Clone)]
+struct Slope {
+	num: i32,
+	den: i32,
+}
 
-			r += r_step;
-			error += delta_c;
-			if error > criterion {
-				error -= delta_r;
-				c += c_step;
-			}
-		} 	
-	} else {
-		let criterion = delta_c / 2;
-		loop {
-			if c_step > 0 && c >= c_end + c_step {
-				break;
-			} else if c_step < 0 && c <= c_end + c_step {
-				break;
-			}
+impl Slope {
+	fn new(num: i32, den: i32) -> Slope {
+		Slope { num, den }
+	}
+
+	// The slope to the left edge of a square
+	fn to_edge(depth: i32, col: i32) -> Slope {
+		Slope::new(2 * col - 1, 2 * depth)
+	}
+}
+
+// depth * slope rounded to the nearest integer, with ties going up
+fn round_ties_up(depth: i32, s: Slope) -> i32 {
+	(2 * depth * s.num + s.den).div_euclid(2 * s.den)
+}
+
+// depth * slope rounded to the nearest integer, with ties going down
+fn round_ties_down(depth: i32, s: Slope) -> i32 {
+	-(-(2 * depth * s.num - s.den)).div_euclid(2 * s.den)
+}
+
+// Is the centre of the square between the two slopes?
+fn is_symmetric(depth: i32, col: i32, start: Slope, end: Slope) -> bool {
+	col * start.den >= depth * start.num && col * end.den <= depth * end.num
+}
 
-			if !map::in_bounds(map, r, c) {
-				return;
+struct Quadrant<'a> {
+	map: &'a Map,
+	origin: (i32, i32),
+	dir: usize,
+	visible: &'a mut HashSet<(usize, usize)>,
+}
+
+impl<'a> Quadrant<'a> {
+	// Convert a (depth, col) in the quadrant to a map (row, col)
+	fn transform(&self, depth: i32, col: i32) -> (i32, i32) {
+		match self.dir {
+			0 => (self.origin.0 - depth, self.origin.1 + col), // north
+			1 => (self.origin.0 + depth, self.origin.1 + col), // south
+			2 => (self.origin.0 + col, self.origin.1 + depth), // east
+			_ => (self.origin.0 + col, self.origin.1 - depth), // west
+		}
+	}
+
+	fn sight(&self, r: i32, c: i32) -> Sight {
+		if !self.map.in_bounds(r, c) {
+			Sight::Blocked
+		} else {
+			let tile = self.map[(r as usize, c as usize)];
+			if !map::is_clear(tile) {
+				Sight::Blocked
+			} else if tile == map::Tile::Tree {
+				Sight::Tree
+			} else {
+				Sight::Clear
 			}
+		}
+	}
+
+	fn scan(&mut self, depth: i32, start: Slope, end: Slope, max_depth: i32) {
+		if depth > max_depth {
+			return;
+		}
 
-			let vm_r = (r - r1 + 10) as usize;
-			let vm_c = (c - c1 + 20) as usize;
-			v_matrix[vm_r][vm_c] = calc_actual_tile(r as usize, c as usize, map, npcs, items);
+		let mut start = start;
+		let mut prev: Option<Sight> = None;
+		for col in round_ties_up(depth, start)..=round_ties_down(depth, end) {
+			let (r, c) = self.transform(depth, col);
+			let sight = self.sight(r, c);
 
-			if !map::is_clear(map[r as usize][c as usize]) {
-				return;
+			if (sight == Sight::Blocked || is_symmetric(depth, col, start, end))
+					&& self.map.in_bounds(r, c) {
+				self.visible.insert((r as usize, c as usize));
 			}
-		
-			// Same as above, trees partially block vision instead of cutting it off
-			// altogether
-			if map::Tile::Tree == map[r as usize][c as usize] && !(r == r1 && c == c1) {
-				if c_step > 0 {
-					c_end -= 3;
-				} else {
-					c_end += 3;
+
+			if let Some(p) = prev {
+				if p != sight {
+					// We've reached the end of a run of squares. If we could see
+					// through them, look past them into the next row.
+					if p != Sight::Blocked {
+						self.scan(depth + 1, start, Slope::to_edge(depth, col),
+							max_depth - p.penalty());
+					}
+					start = Slope::to_edge(depth, col);
 				}
 			}
-			
-			c += c_step;
-			error += delta_r;
-			if error > criterion {
-				error -= delta_c;
-				r += r_step;
+			prev = Some(sight);
+		}
+
+		if let Some(p) = prev {
+			if p != Sight::Blocked {
+				self.scan(depth + 1, start, end, max_depth - p.penalty());
 			}
 		}
 	}
 }
 
-// not yet taking into account objects on the ground and monsters...
+// The set of squares visible from (row, col) out to radius squares away.
+// With no trees in the way, visibility is symmetric: if you can see a square,
+// whoever's standing there can see you.
+pub fn visible_squares(map: &Map, row: usize, col: usize, radius: usize) -> HashSet<(usize, usize)> {
+	let mut visible = HashSet::new();
+	visible.insert((row, col));
+
+	for dir in 0..4 {
+		let mut quadrant = Quadrant {
+			map, origin: (row as i32, col as i32), dir, visible: &mut visible
+		};
+		quadrant.scan(1, Slope::new(-1, 1), Slope::new(1, 1), radius as i32);
+	}
+
+	visible
+}
+
+// Everything the player can see gets remembered, whether or not it fits in
+// the viewport (otherwise how much of the map they know would depend on the
+// window size). Then squares in view are drawn as they are and squares out of
+// view are drawn from the player's memory of them, if they have one. NPCs
+// aren't remembered since they won't stay put.
 pub fn calc_v_matrix(
-		map: &Vec<Vec<map::Tile>>,
-		npcs: &NPCTable,
-		items: &ItemsTable,
+		level: &mut Level,
 		player_row: usize, player_col: usize,
-		height: usize, width: usize) -> Vec<Vec<map::Tile>> {
+		height: usize, width: usize, radius: usize) -> Vec<Vec<map::Tile>> {
+	let Level { map, npcs, items } = level;
 	let mut v_matrix: Vec<Vec<map::Tile>> = Vec::new();
 	for _ in 0..height {
 		v_matrix.push(vec![map::Tile::Blank; width]);
@@ -144,6 +192,11 @@ pub fn calc_v_matrix(
 
 	let fov_center_r = height / 2;
 	let fov_center_c = width / 2;
+	let visible = visible_squares(map, player_row, player_col, radius);
+	for &(r, c) in &visible {
+		let tile = calc_remembered_tile(r, c, map, items);
+		map.remember(r, c, tile);
+	}
 
 	for row in 0..height {
 		for col in 0..width {
@@ -152,8 +205,18 @@ pub fn calc_v_matrix(
 			let actual_r: i32 = player_row as i32 + offset_r;
 			let actual_c: i32 = player_col as i32 + offset_c;
 
-			mark_visible(player_row as i32, player_col as i32,
-				actual_r as i32, actual_c as i32, map, npcs, items, &mut v_matrix);
+			if !map.in_bounds(actual_r, actual_c) {
+				continue;
+			}
+
+			let r = actual_r as usize;
+			let c = actual_c as usize;
+			if visible.contains(&(r, c)) {
+				v_matrix[row][col] = calc_actual_tile(r, c, map, npcs, items);
+			} else if map.is_explored(r, c) {
+				let (ch, color) = display::tile_glyph(map.recall(r, c));
+				v_matrix[row][col] = map::Tile::Remembered(color, ch);
+			}
 		}
 	}
 	
@@ -161,3 +224,96 @@ pub fn calc_v_matrix(
 
 	v_matrix
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::GameRng;
+	use rand::{Rng, SeedableRng};
+	use std::collections::HashMap;
+
+	#[test]
+	fn open_ground_sees_to_radius() {
+		let map = Map::new(30, 30, map::Tile::Grass);
+		let visible = visible_squares(&map, 15, 15, 5);
+
+		assert_eq!(visible.len(), 11 * 11);
+		assert!(visible.contains(&(10, 10)));
+		assert!(!visible.contains(&(9, 15)));
+	}
+
+	#[test]
+	fn walls_block_sight() {
+		let mut map = Map::new(30, 30, map::Tile::Grass);
+		for c in 10..21 {
+			map[(12, c)] = map::Tile::Wall;
+		}
+		let visible = visible_squares(&map, 15, 15, 10);
+
+		assert!(visible.contains(&(12, 15)));
+		assert!(!visible.contains(&(11, 15)));
+		assert!(!visible.contains(&(5, 15)));
+		assert!(visible.contains(&(25, 15)));
+	}
+
+	#[test]
+	fn trees_reduce_visibility() {
+		let mut map = Map::new(30, 30, map::Tile::Grass);
+		map[(14, 15)] = map::Tile::Tree;
+		let visible = visible_squares(&map, 15, 15, 10);
+
+		assert!(visible.contains(&(14, 15)));
+		assert!(visible.contains(&(8, 15)));
+		assert!(!visible.contains(&(5, 15)));
+		assert!(visible.contains(&(25, 15)));
+	}
+
+	#[test]
+	fn standing_in_trees_doesnt_reduce_visibility() {
+		let mut map = Map::new(30, 30, map::Tile::Grass);
+		map[(15, 15)] = map::Tile::Tree;
+		let visible = visible_squares(&map, 15, 15, 10);
+
+		assert!(visible.contains(&(5, 15)));
+	}
+
+	#[test]
+	fn visibility_is_symmetric() {
+		let mut rng = GameRng::seed_from_u64(25);
+		for _ in 0..5 {
+			let mut map = Map::new(25, 25, map::Tile::Grass);
+			for r in 0..25 {
+				for c in 0..25 {
+					if rng.gen_range(0, 4) == 0 {
+						map[(r, c)] = map::Tile::Wall;
+					}
+				}
+			}
+
+			let open = map.coords()
+				.filter(|&(r, c)| map[(r, c)] != map::Tile::Wall)
+				.collect::<Vec<(usize, usize)>>();
+			let sights = open.iter()
+				.map(|&(r, c)| ((r, c), visible_squares(&map, r, c, 30)))
+				.collect::<HashMap<(usize, usize), HashSet<(usize, usize)>>>();
+			for (a, visible) in &sights {
+				for b in visible.iter().filter(|b| sights.contains_key(b)) {
+					assert!(sights[b].contains(a), "{:?} sees {:?} but not the reverse", a, b);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn memory_doesnt_depend_on_viewport() {
+		let mut small = Level::new(Map::new(30, 30, map::Tile::Grass));
+		let mut large = Level::new(Map::new(30, 30, map::Tile::Grass));
+		let v_matrix = calc_v_matrix(&mut small, 15, 15, 11, 21, 10);
+		calc_v_matrix(&mut large, 15, 15, 31, 31, 10);
+
+		assert_eq!((v_matrix.len(), v_matrix[0].len()), (11, 21));
+		assert!(small.map.is_explored(5, 15));
+		assert!(!small.map.is_explored(4, 15));
+		assert_eq!(small.map, large.map);
+	}
+}
@@ -0,0 +1,231 @@
+use std::collections::{HashSet, VecDeque};
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use std::path::Path;
+
+use crate::display::{self, Color, Frontend, ESC_CH, BLACK, WHITE};
+#[cfg(test)]
+use crate::display::{BACKSPACE_CH, CTRL_H_CH};
+use crate::map;
+use super::Cmd;
+#[cfg(test)]
+use super::{FOV_WIDTH, FOV_HEIGHT};
+
+// One drawn screen's worth of characters and their colours, laid out the
+// same as the SDL window: row 0 is the message line and the map starts on
+// row 1.
+#[derive(Debug, Clone)]
+pub struct Frame {
+	pub cells: Vec<Vec<(char, Color)>>,
+	// Where the cursor was drawn, in the same coordinates as cells
+	pub cursor: Option<(usize, usize)>,
+}
+
+impl Frame {
+	fn new(height: usize, width: usize) -> Frame {
+		Frame { cells: vec![vec![(' ', BLACK); width]; height], cursor: None }
+	}
+
+	fn write_line(&mut self, row: usize, line: &str) {
+		if row >= self.cells.len() {
+			return;
+		}
+
+		for (col, ch) in line.chars().enumerate() {
+			if col >= self.cells[row].len() {
+				break;
+			}
+			self.cells[row][col] = (ch, WHITE);
+		}
+	}
+
+	// The characters on one row, with trailing blanks trimmed
+	pub fn row_text(&self, row: usize) -> String {
+		let s: String = self.cells[row].iter().map(|c| c.0).collect();
+		s.trim_end().to_string()
+	}
+}
+
+// Key scripts are plain text where each character is a keypress. Whitespace
+// is ignored so a script can be laid out over several lines, # starts a
+// comment that runs to the end of the line, and keys that can't be typed
+// as themselves go in angle brackets: <esc>, <ret>, <bs>, <space>, <c-h>,
+// <hash> and <lt>. Only the tests write key scripts so far.
+#[cfg(test)]
+pub fn parse_key_script(script: &str) -> Result<String, String> {
+	let mut keys = String::from("");
+
+	for line in script.lines() {
+		let mut chars = line.chars();
+		while let Some(ch) = chars.next() {
+			if ch == '#' {
+				break;
+			} else if ch.is_whitespace() {
+				continue;
+			} else if ch == '<' {
+				let mut name = String::from("");
+				loop {
+					match chars.next() {
+						Some('>') => break,
+						Some(c) => name.push(c),
+						None => return Err(format!("Unterminated key name: <{}", name)),
+					}
+				}
+
+				let key = match name.as_str() {
+					"esc" => ESC_CH,
+					"ret" => '\n',
+					"bs" => BACKSPACE_CH,
+					"space" => ' ',
+					"c-h" => CTRL_H_CH,
+					"hash" => '#',
+					"lt" => '<',
+					_ => return Err(format!("Unknown key name: <{}>", name)),
+				};
+				keys.push(key);
+			} else {
+				keys.push(ch);
+			}
+		}
+	}
+
+	Ok(keys)
+}
+
+// A frontend that draws into memory instead of a window and reads its keys
+// from a scripted queue, so the game can be driven without a display. When
+// the script runs dry, every further request for input acts like Escape.
+// Nothing waits at --More-- prompts; they don't consume any input.
+pub struct HeadlessUI {
+	input: VecDeque<char>,
+	fov_height: usize,
+	fov_width: usize,
+	screen_height: usize,
+	screen_width: usize,
+	pub frames: Vec<Frame>,
+	pub v_matrix: Vec<Vec<map::Tile>>,
+	pub cursor: Option<(usize, usize)>,
+}
+
+impl HeadlessUI {
+	#[cfg(test)]
+	pub fn new(input: &str) -> HeadlessUI {
+		HeadlessUI::with_viewport(input, FOV_HEIGHT, FOV_WIDTH)
+	}
+
+	pub fn with_viewport(input: &str, fov_height: usize, fov_width: usize) -> HeadlessUI {
+		let (screen_height, screen_width) = display::screen_size(fov_height, fov_width);
+		HeadlessUI {
+			input: input.chars().collect(),
+			fov_height, fov_width, screen_height, screen_width,
+			frames: Vec::new(),
+			v_matrix: vec![vec![map::Tile::Blank; fov_width]; fov_height],
+			cursor: None,
+		}
+	}
+
+	#[cfg(test)]
+	pub fn from_script_file(path: &Path) -> Result<HeadlessUI, String> {
+		let script = fs::read_to_string(path)
+			.map_err(|e| format!("Error reading key script {}: {}", path.display(), e))?;
+		let keys = parse_key_script(&script)?;
+
+		Ok(HeadlessUI::new(&keys))
+	}
+
+	pub fn last_frame(&self) -> Option<&Frame> {
+		self.frames.last()
+	}
+
+	fn draw_frame(&mut self, msg: &str) {
+		let mut frame = Frame::new(self.screen_height, self.screen_width);
+		frame.write_line(0, msg);
+		// A replay can hand over a v_matrix recorded at a different size, so
+		// only draw what fits
+		for row in 0..self.v_matrix.len().min(self.fov_height) {
+			for col in 0..self.v_matrix[row].len().min(self.fov_width) {
+				frame.cells[row + 1][col] = display::tile_glyph(self.v_matrix[row][col]);
+			}
+		}
+		frame.cursor = self.cursor.map(|(r, c)| (r + 1, c));
+
+		self.frames.push(frame);
+	}
+}
+
+impl Frontend for HeadlessUI {
+	fn wait_for_key_input(&mut self) -> Option<char> {
+		match self.input.pop_front() {
+			Some(ESC_CH) | None => None,
+			Some(ch) => Some(ch),
+		}
+	}
+
+	fn get_command(&mut self) -> Cmd {
+		loop {
+			match self.input.pop_front() {
+				None => return Cmd::Exit,
+				Some(ch) => {
+					if let Some(cmd) = display::key_to_cmd(ch) {
+						return cmd;
+					}
+				},
+			}
+		}
+	}
+
+	fn viewport_size(&self) -> (usize, usize) {
+		(self.fov_height, self.fov_width)
+	}
+
+	fn set_v_matrix(&mut self, v_matrix: Vec<Vec<map::Tile>>) {
+		self.v_matrix = v_matrix;
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		self.cursor = cursor;
+	}
+
+	fn write_long_msg(&mut self, lines: &[String], _small_text: bool) {
+		let display_lines = self.screen_height;
+		let mut frame = Frame::new(self.screen_height, self.screen_width);
+		let mut curr_row = 0;
+		for j in 0..lines.len() {
+			frame.write_line(curr_row, &lines[j]);
+			curr_row += 1;
+
+			if curr_row == display_lines - 2 && j < lines.len() - 1 {
+				frame.write_line(curr_row + 1, "-- Press space to continue --");
+				self.frames.push(frame);
+				frame = Frame::new(self.screen_height, self.screen_width);
+				curr_row = 0;
+			}
+		}
+
+		frame.write_line(curr_row + 1, "-- Press space to continue --");
+		self.frames.push(frame);
+	}
+
+	fn write_screen(&mut self, msgs: &mut VecDeque<String>) {
+		for line in display::paginate_msgs(msgs, self.screen_width) {
+			self.draw_frame(&line);
+		}
+	}
+
+	fn draw_menu(&mut self, menu: &[String], answers: &HashSet<u8>) {
+		let mut frame = Frame::new(self.screen_height, self.screen_width);
+		for (j, line) in menu.iter().enumerate() {
+			if j > 0 && answers.contains(&(j as u8 - 1)) {
+				let mut s = String::from("\u{2713} ");
+				s.push_str(line);
+				frame.write_line(j, &s);
+			} else {
+				frame.write_line(j, line);
+			}
+		}
+		frame.write_line(menu.len() + 2, "Select one or more options, then hit Return.");
+
+		self.frames.push(frame);
+	}
+}
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 
-use sdl2::pixels::Color;
+use crate::display::Color;
 
 pub trait TileInfo {
 	fn get_tile_info(&self) -> (Color, char);
@@ -24,6 +24,31 @@ impl Inventory {
 		Inventory { next_slot: 'a', inv: HashMap::new() }
 	}
 
+	// Put an inventory back together exactly as it was, slots and all (for
+	// loading saved games)
+	pub fn restore(next_slot: char, slots: Vec<(char, Item, u8)>) -> Inventory {
+		let mut inv = HashMap::new();
+		for (slot, item, count) in slots {
+			inv.insert(slot, (item, count));
+		}
+
+		Inventory { next_slot, inv }
+	}
+
+	pub fn get_next_slot(&self) -> char {
+		self.next_slot
+	}
+
+	// Everything being carried as (slot, item, count), in slot order
+	pub fn get_slots(&self) -> Vec<(char, &Item, u8)> {
+		let mut slots = self.inv.iter()
+			.map(|(slot, v)| (*slot, &v.0, v.1))
+			.collect::<Vec<(char, &Item, u8)>>();
+		slots.sort_by_key(|v| v.0);
+
+		slots
+	}
+
 	fn set_next_slot(&mut self) {
 		let mut slot = self.next_slot;
 		
@@ -66,6 +91,7 @@ impl Inventory {
 		for _ in 0..max {
 			let mut i = Item { name:v.0.name.clone(), ..v.0 }; 
 			i.prev_slot = slot;
+			i.equipped = false;
 			items.push(i);
 		}
 
@@ -80,6 +106,7 @@ impl Inventory {
 			self.next_slot = slot;
 		}
 		v.0.prev_slot = slot;
+		v.0.equipped = false;
 
 		v.0
 	}
@@ -119,6 +146,37 @@ impl Inventory {
 		}
 	}
 
+	pub fn get_wielded(&self) -> Option<&Item> {
+		self.inv.values()
+			.map(|v| &v.0)
+			.find(|i| i.equipped)
+	}
+
+	// Wield the weapon in slot, putting away whatever was wielded before.
+	// Returns a message explaining why not if the slot can't be wielded.
+	pub fn wield(&mut self, slot: char) -> Result<String, String> {
+		match self.inv.get(&slot) {
+			None => return Err(String::from("You do not have that item.")),
+			Some(v) => {
+				if v.0.equipped {
+					return Err(String::from("You are already wielding that!"));
+				}
+				match v.0.item_type {
+					ItemType::Weapon => { },
+					_ => return Err(String::from("That's not a weapon!")),
+				}
+			},
+		}
+
+		for v in self.inv.values_mut() {
+			v.0.equipped = false;
+		}
+		let v = self.inv.get_mut(&slot).unwrap();
+		v.0.equipped = true;
+
+		Ok(format!("You are now wielding the {}.", v.0.name))
+	}
+
 	pub fn get_menu(&self) -> Vec<String> {
 		let mut menu = Vec::new();
 
@@ -141,6 +199,9 @@ impl Inventory {
 				s.push_str(" x");
 				s.push_str(&val.1.to_string());
 			}
+			if val.0.equipped {
+				s.push_str(" (in hand)");
+			}
 			menu.push(s);
 		}
 
@@ -166,6 +227,18 @@ impl ItemsTable {
 		stack.push_front(item);
 	}
 
+	// Every pile of items as its location and the items from the top down,
+	// sorted by location
+	pub fn get_piles(&self) -> Vec<((usize, usize), Vec<&Item>)> {
+		let mut piles = self.table.iter()
+			.filter(|(_, stack)| !stack.is_empty())
+			.map(|(loc, stack)| (*loc, stack.iter().collect()))
+			.collect::<Vec<((usize, usize), Vec<&Item>)>>();
+		piles.sort_by_key(|p| p.0);
+
+		piles
+	}
+
 	pub fn count_at(&self, r: usize, c: usize) -> u8 {
 		let res = if !self.table.contains_key(&(r, c)) {
 			0
@@ -229,14 +302,25 @@ pub struct Item {
 	pub symbol: char,
 	pub color: Color,
 	pub stackable: bool,
-	pub prev_slot: char
+	pub prev_slot: char,
+	pub dmg: u8,
+	pub equipped: bool,
 }
 
 impl Item {
 	pub fn new(name: &str, item_type: ItemType, w: u8, stackable: bool,
 			sym: char, color: Color) -> Item {
 		Item { name: String::from(name), 
-			item_type, weight: w, symbol: sym, color, stackable, prev_slot: '\0' }
+			item_type, weight: w, symbol: sym, color, stackable, prev_slot: '\0',
+			dmg: 0, equipped: false }
+	}
+
+	// dmg is the most damage the weapon does with a single hit
+	pub fn new_weapon(name: &str, w: u8, dmg: u8, sym: char, color: Color) -> Item {
+		let mut i = Item::new(name, ItemType::Weapon, w, false, sym, color);
+		i.dmg = dmg;
+
+		i
 	}
 }
 
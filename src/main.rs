@@ -1,33 +1,65 @@
 extern crate rand;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
 mod actor;
+mod combat;
+mod dijkstra;
 mod display;
 mod fov;
+mod headless;
 mod items;
-#[allow(dead_code)]
 mod map;
-#[allow(dead_code)]
 mod pathfinding;
+mod replay;
+mod save;
+#[cfg(feature = "sdl")]
+mod sdl_ui;
+mod terminal;
+mod world;
 
-use crate::actor::{Act, Player};
-use crate::display::GameUI;
+use crate::actor::{Act, Action, Player, ACTION_COST};
+use crate::dijkstra::DijkstraMap;
+use crate::display::Frontend;
+use crate::headless::HeadlessUI;
 use crate::items::ItemsTable;
+#[cfg(feature = "sdl")]
+use crate::sdl_ui::GameUI;
+use crate::terminal::TerminalUI;
+use crate::world::{Level, World};
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 use std::cell::RefCell;
-use std::collections::{HashMap, VecDeque};
-use std::path::Path;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Duration;
 
 const MSG_HISTORY_LENGTH: usize = 50;
 const FOV_WIDTH: usize = 41;
 const FOV_HEIGHT: usize = 21;
+const FOV_RADIUS: usize = 20;
+const MIN_FOV_WIDTH: usize = 21;
+const MIN_FOV_HEIGHT: usize = 11;
+const SAVE_FILE: &str = "game.sav";
+// Milliseconds between commands when watching a replay
+const REPLAY_DELAY: u64 = 100;
+const CAVE_COUNT: usize = 3;
+const CAVE_WIDTH: usize = 60;
+const CAVE_HEIGHT: usize = 30;
 
-pub type Map = Vec<Vec<map::Tile>>;
+use crate::map::Map;
 type NPCTable = HashMap<(usize, usize), Rc<RefCell<dyn actor::Act>>>;
 
+// The game's random number generator. rand doesn't promise StdRng will give
+// the same numbers from one version (or platform) to the next, so I'm naming
+// the algorithm to make sure a seed always means the same game.
+pub type GameRng = ChaCha8Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Cmd {
 	Exit,
 	MoveN,
@@ -42,20 +74,51 @@ enum Cmd {
 	PickUp,
 	ShowInventory,
 	DropItem,
+	Wield,
+	Run(&'static str), // keep moving in a direction, as for get_move_tuple()
+	Explore,
+	Look,
+	Travel,
+	TravelTo(usize, usize), // a square in the viewport, say from a mouse click
+	Down,
+	Up,
+	Redraw, // the frontend's layout changed and it needs a fresh v_matrix
+}
+
+// Why main_loop() returned: the game came to an end, or the player took
+// the stairs and is off to another level
+#[derive(Debug, PartialEq)]
+enum Outcome {
+	Quit,
+	Died,
+	Stairs,
 }
 
 pub struct GameState {
 	player: Player,
 	msg_buff: VecDeque<String>,
 	msg_history: VecDeque<(String, u32)>,
+	turn: u32,
+	killed_by: Option<String>,
+	// Dijkstra maps for monsters heading towards or running away from the
+	// player. Worked out fresh each time the NPCs get to move.
+	to_player: Option<DijkstraMap>,
+	from_player: Option<DijkstraMap>,
+	// Every random roll in the game comes from here, so a game started
+	// from the same seed plays out the same way
+	rng: GameRng,
+	// Which level of the world the player is on
+	level: usize,
 }
 
 impl GameState {
-	pub fn new(name: String) -> GameState {
+	pub fn new(name: String, seed: u64) -> GameState {
 		let mut player = Player::new(name);
 
 		GameState {player, msg_buff: VecDeque::new(),
-			msg_history: VecDeque::new() }
+			msg_history: VecDeque::new(), turn: 0, killed_by: None,
+			to_player: None, from_player: None, rng: GameRng::seed_from_u64(seed),
+			level: 0 }
 	}
 
 	pub fn write_msg_buff(&mut self, msg: &str) {
@@ -101,21 +164,33 @@ fn get_move_tuple(mv: &str) -> (i16, i16) {
 	res
 }
 
-fn do_move(map: &Map, state: &mut GameState, npcs: &NPCTable, items: &ItemsTable, dir: &str) {
+// Flavour text for wading in, which isn't worth stopping a run or travel for
+const SPLASH_MSG: &str = "You splash in the shallow water.";
+
+// Returns true if the player actually moved or attacked (and so used up their turn)
+fn do_move(map: &Map, state: &mut GameState, npcs: &mut NPCTable, items: &ItemsTable, dir: &str) -> bool {
 	let mv = get_move_tuple(dir);
 	let next_row = state.player.row as i16 + mv.0;
 	let next_col = state.player.col as i16 + mv.1;
-	let tile = map[next_row as usize][next_col as usize];
+	if !map.in_bounds(next_row as i32, next_col as i32) {
+		state.write_msg_buff("You cannot go that way.");
+		return false;
+	}
+	let tile = map[(next_row as usize, next_col as usize)];
+	let from = map[(state.player.row, state.player.col)];
 	
 	if npcs.contains_key(&(next_row as usize, next_col as usize)) {
-		state.write_msg_buff("There is someone in your way!");
+		combat::player_attacks(state, npcs, (next_row as usize, next_col as usize));
+		true
 	}
 	else if map::is_passable(tile) {
 		state.player.col = next_col as usize;
 		state.player.row = next_row as usize;
 
-		if tile == map::Tile::Water {
-			state.write_msg_buff("You splash in the shallow water.");
+		// Only mention the water on the way in, otherwise wading along would
+		// be one message after another (and interrupt running every step)
+		if tile == map::Tile::Water && from != map::Tile::Water {
+			state.write_msg_buff(SPLASH_MSG);
 		} 
 
 		let items_count = items.count_at(state.player.row, state.player.col);
@@ -126,16 +201,20 @@ fn do_move(map: &Map, state: &mut GameState, npcs: &NPCTable, items: &ItemsTable
 		} else if items_count > 1 {
 			state.write_msg_buff("You see a few items here.");
 		}	
+
+		true
 	} else  {
 		if tile == map::Tile::DeepWater {
 			state.write_msg_buff("You cannot swim!");
 		} else {
 			state.write_msg_buff("You cannot go that way.");
 		}
+
+		false
 	}
 }
 
-fn show_message_history(state: &GameState, gui: &mut GameUI) {
+fn show_message_history(state: &GameState, gui: &mut dyn Frontend) {
 	let mut lines = Vec::new();
 	lines.push("".to_string());
 	for j in 0..state.msg_history.len() {
@@ -151,6 +230,14 @@ fn show_message_history(state: &GameState, gui: &mut GameUI) {
 	gui.write_long_msg(&lines, true);
 }
 
+fn with_article(name: &str) -> String {
+	if name.starts_with(|c| "aeiou".contains(c)) {
+		format!("an {}", name)
+	} else {
+		format!("a {}", name)
+	}
+}
+
 // Attempt to reasonably pluralize names
 // I'm going to assume a fairly standard form of names of things that
 // can be pluralized. Like, "foo of bar" so I can asssume the result will
@@ -183,10 +270,11 @@ fn pluralize(name: &str, count: u8) -> String{
 	result	
 }
 
-fn drop_item(state: &mut GameState, items: &mut ItemsTable, gui: &mut GameUI) {
+// Returns true if something was dropped (and so the player used up their turn)
+fn drop_item(state: &mut GameState, items: &mut ItemsTable, gui: &mut dyn Frontend) -> bool {
 	if state.player.inventory.get_menu().len() == 0 {
 		state.write_msg_buff("You are empty handed.");
-		return
+		return false;
 	}
 
 	match gui.query_single_response("Drop what?") {
@@ -194,6 +282,7 @@ fn drop_item(state: &mut GameState, items: &mut ItemsTable, gui: &mut GameUI) {
 			let count = state.player.inventory.count_in_slot(ch);
 			if count == 0 {
 				state.write_msg_buff("You do not have that item.");
+				false
 			} else if count > 1 {
 				match gui.query_natural_num("Drop how many?") {
 					Some(v) => {
@@ -205,51 +294,95 @@ fn drop_item(state: &mut GameState, items: &mut ItemsTable, gui: &mut GameUI) {
 							for item in pile {
 								items.add(state.player.row, state.player.col, item);
 							}
+							true
 						} else {
 							state.write_msg_buff("Nevermind.");
+							false
 						}
 					},
-					None => state.write_msg_buff("Nevermind."),
+					None => {
+						state.write_msg_buff("Nevermind.");
+						false
+					},
 				}
 			} else {
 				let item = state.player.inventory.remove(ch);
 				let s = format!("You drop the {}.", item.name);
 				items.add(state.player.row, state.player.col, item);	
 				state.write_msg_buff(&s);
+				true
 			}	
 		},
-		None => state.write_msg_buff("Nevermind."),
+		None => {
+			state.write_msg_buff("Nevermind.");
+			false
+		},
 	}
 }
 
-fn pick_up(state: &mut GameState, items: &mut ItemsTable, gui: &mut GameUI) {
+// Returns true if something was picked up (and so the player used up their turn)
+fn pick_up(state: &mut GameState, items: &mut ItemsTable, gui: &mut dyn Frontend) -> bool {
 	let item_count = items.count_at(state.player.row, state.player.col);
 	if item_count == 0 {
 		state.write_msg_buff("There is nothing here to pick up.");
+		false
 	} else if item_count == 1 {
 		let item = items.get_at(state.player.row, state.player.col);
 		let s = format!("You pick up the {}.", item.name);
 		state.player.inventory.add(item);
 		state.write_msg_buff(&s);
+		true
 	} else {
 		let mut menu = items.get_menu(state.player.row, state.player.col);
 		menu.insert(0, "Pick up what: (* to get everything)".to_string());
 		let answers = gui.menu_picker(&menu, menu.len() as u8);
 		match answers {
-			None => state.write_msg_buff("Nevermind."), // Esc was pressed
+			None => { // Esc was pressed
+				state.write_msg_buff("Nevermind.");
+				false
+			},
 			Some(v) => {
 				let picked_up = items.get_many_at(state.player.row, state.player.col, &v);
+				let took_turn = !picked_up.is_empty();
 				for item in picked_up {
 					let s = format!("You pick up the {}.", item.name);
 					state.player.inventory.add(item);
 					state.write_msg_buff(&s);
 				}
+				took_turn
 			},
 		}
 	}
 }
 
-fn show_inventory(state: &mut GameState, gui: &mut GameUI) {
+// Returns true if the player changed weapons (and so used up their turn)
+fn wield(state: &mut GameState, gui: &mut dyn Frontend) -> bool {
+	if state.player.inventory.get_menu().is_empty() {
+		state.write_msg_buff("You are empty handed.");
+		return false;
+	}
+
+	match gui.query_single_response("Wield what?") {
+		Some(ch) => {
+			match state.player.inventory.wield(ch) {
+				Ok(msg) => {
+					state.write_msg_buff(&msg);
+					true
+				},
+				Err(msg) => {
+					state.write_msg_buff(&msg);
+					false
+				},
+			}
+		},
+		None => {
+			state.write_msg_buff("Nevermind.");
+			false
+		},
+	}
+}
+
+fn show_inventory(state: &mut GameState, gui: &mut dyn Frontend) {
 	let mut menu = state.player.inventory.get_menu();
 
 	if menu.len() == 0 {
@@ -260,82 +393,100 @@ fn show_inventory(state: &mut GameState, gui: &mut GameUI) {
 	}
 }
 
-fn show_intro(gui: &mut GameUI) {
+fn show_intro(gui: &mut dyn Frontend) {
 	let mut lines = vec!["Welcome to a rogulike UI prototype!".to_string(), "".to_string()];
 	lines.push("You can move around with vi-style keys and bump".to_string());
 	lines.push("into water and mountains.".to_string());
 	lines.push("".to_string());
-	lines.push("There are no monsters or anything yet, though!".to_string());
+	lines.push("Watch out, though. There are orcs about!".to_string());
 	
 	gui.write_long_msg(&lines, true);
 }
 
-fn add_monster(map: &Map, state: &mut GameState, npcs: &mut NPCTable) {
-	let mut row = 0;
-	let mut col = 0;
-	loop {
-		row = rand::thread_rng().gen_range(0, map.len());
-		col = rand::thread_rng().gen_range(0, map[0].len());
+fn show_game_over(state: &GameState, gui: &mut dyn Frontend) {
+	let mut lines = vec!["".to_string()];
+	match &state.killed_by {
+		Some(killer) => {
+			lines.push(format!("{} was killed by {}.", state.player.name, with_article(killer)));
+		},
+		None => lines.push(format!("{} died.", state.player.name)),
+	}
+	let turns = if state.turn == 1 { "turn" } else { "turns" };
+	lines.push(format!("They survived for {} {}.", state.turn, turns));
+	lines.push("".to_string());
 
-		let tile = map[row][col];
-		if map::is_passable(tile) { break; };
-	}	
-	
-	let mut m = actor::Monster::new(13, 25, 'o', row, col, display::BLUE);
-	npcs.insert((row, col), Rc::new(RefCell::new(m)));
+	let inventory = state.player.inventory.get_menu();
+	if inventory.is_empty() {
+		lines.push("They died empty-handed.".to_string());
+	} else {
+		lines.push("They were carrying:".to_string());
+		lines.extend(inventory);
+	}
+
+	gui.write_long_msg(&lines, true);
 }
 
-fn add_test_item(map: &Map, items: &mut ItemsTable) {
-	let mut row = 0;
-	let mut col = 0;
+// A random square on the level that can be walked on and doesn't already
+// have a monster on it, nor is one of the squares in avoid (the player, the
+// stairs, etc.)
+fn random_free_sq(level: &Level, avoid: &[(usize, usize)], rng: &mut GameRng) -> (usize, usize) {
 	loop {
-		row = rand::thread_rng().gen_range(0, map.len());
-		col = rand::thread_rng().gen_range(0, map[0].len());
+		let row = rng.gen_range(0, level.map.height());
+		let col = rng.gen_range(0, level.map.width());
 
-		let tile = map[row][col];
-		if map::is_passable(tile) { break; };
-	}	
+		if map::is_passable(level.map[(row, col)]) && !level.npcs.contains_key(&(row, col))
+				&& !avoid.contains(&(row, col)) {
+			return (row, col);
+		}
+	}
+}
 
-	let i = items::Item::new("draught of rum", items::ItemType::Drink, 1, true,
-		'!', display::BROWN);
-	items.add(row, col, i);	
+fn add_monster(level: &mut Level, avoid: &[(usize, usize)], rng: &mut GameRng) {
+	let (row, col) = random_free_sq(level, avoid, rng);
+	let m = actor::Monster::new("orc", 13, 8, 3, 'o', (row, col), display::BLUE);
+	level.npcs.insert((row, col), Rc::new(RefCell::new(m)));
+}
 
-	let i = items::Item::new("rusty cutlass", items::ItemType::Weapon, 3, false,
-		'|', display::WHITE);
-	items.add(row, col, i);	
+fn add_test_item(level: &mut Level, rng: &mut GameRng) {
+	let (row, col) = random_free_sq(level, &[], rng);
 
 	let i = items::Item::new("draught of rum", items::ItemType::Drink, 1, true,
 		'!', display::BROWN);
-	items.add(row, col + 1, i);	
+	level.items.add(row, col, i);	
 
-	let i = items::Item::new("draught of rum", items::ItemType::Drink, 1, true,
-		'!', display::BROWN);
-	items.add(row + 1, col, i);	
+	let i = items::Item::new_weapon("rusty cutlass", 3, 6, '|', display::WHITE);
+	level.items.add(row, col, i);	
 
-	let i = items::Item::new("draught of gin", items::ItemType::Drink, 1, true,
-		'!', display::WHITE);
-	items.add(row - 1, col, i);	
+	// The rest get scattered around it, so long as there's floor there. (The
+	// square we picked might be right on the edge of a cave.)
+	let extras = vec![
+		(0, 1, "draught of rum", display::BROWN),
+		(1, 0, "draught of rum", display::BROWN),
+		(-1, 0, "draught of gin", display::WHITE),
+	];
+	for (dr, dc, name, colour) in extras {
+		let r = row as i32 + dr;
+		let c = col as i32 + dc;
+		if !level.map.in_bounds(r, c) || !map::is_passable(level.map[(r as usize, c as usize)]) {
+			continue;
+		}
+
+		let i = items::Item::new(name, items::ItemType::Drink, 1, true, '!', colour);
+		level.items.add(r as usize, c as usize, i);
+	}
 }
 
-fn run(map: &Map) {
-    let ttf_context = sdl2::ttf::init()
-		.expect("Error creating ttf context on start-up!");
-	let font_path: &Path = Path::new("DejaVuSansMono.ttf");
-    let font = ttf_context.load_font(font_path, 24)
-		.expect("Error loading game font!");
-	let sm_font = ttf_context.load_font(font_path, 18)
-		.expect("Error loading small game font!");
-	let mut gui = GameUI::init(&font, &sm_font)
-		.expect("Error initializing GameUI object.");
+// Sets up a new game: the island, with a few cave entrances leading down
+// to caves of their own
+fn new_game(player_name: &str, seed: u64) -> save::Game {
+	let mut state = GameState::new(player_name.to_string(), seed);
+	let mut world = World::new();
 
-	show_intro(&mut gui);
-		
-	let player_name = gui.query_user("Who are you?");
-	let mut state = GameState::new(player_name);
+	let map = map::generate_island(65, &mut state.rng);
 	loop {
-		let r = rand::thread_rng().gen_range(1, map.len() - 1);
-		let c = rand::thread_rng().gen_range(1, map.len() - 1);
-		match map[r][c] {
+		let r = state.rng.gen_range(1, map.height() - 1);
+		let c = state.rng.gen_range(1, map.width() - 1);
+		match map[(r, c)] {
 			map::Tile::Water | map::Tile::Wall | map::Tile::DeepWater |
 			map::Tile::Mountain | map::Tile::SnowPeak => { continue; },
 			_ => {
@@ -345,88 +496,1852 @@ fn run(map: &Map) {
 			}
 		}
 	}
-	
-	let mut npcs: NPCTable = HashMap::new();
-	add_monster(map, &mut state, &mut npcs);
 
-	let mut items = ItemsTable::new();
-	add_test_item(map, &mut items);
+	let mut surface = Level::new(map);
+	add_monster(&mut surface, &[(state.player.row, state.player.col)], &mut state.rng);
+	add_test_item(&mut surface, &mut state.rng);
+	world.add_level(surface);
+
+	for _ in 0..CAVE_COUNT {
+		// Cave entrances go on dry land, somewhere the player isn't standing
+		// and there isn't already a staircase
+		let top = loop {
+			let sq = random_free_sq(&world.levels[0], &[(state.player.row, state.player.col)],
+				&mut state.rng);
+			let tile = world.levels[0].map[sq];
+			if tile != map::Tile::Water && tile != map::Tile::StairsDown {
+				break sq;
+			}
+		};
+
+		// Pick where the stairs come out first so the monster doesn't end up
+		// waiting right on top of them
+		let mut cave = Level::new(map::generate_cave(CAVE_WIDTH, CAVE_HEIGHT, &mut state.rng));
+		let bottom = random_free_sq(&cave, &[], &mut state.rng);
+		add_monster(&mut cave, &[bottom], &mut state.rng);
+		add_test_item(&mut cave, &mut state.rng);
+
+		let level = world.add_level(cave);
+		world.add_stairs((0, top.0, top.1), (level, bottom.0, bottom.1));
+	}
 
 	state.write_msg_buff(&format!("Welcome, {}!", state.player.name));
-	gui.v_matrix = fov::calc_v_matrix(&map, &npcs, &items,
-		state.player.row, state.player.col, FOV_HEIGHT, FOV_WIDTH);
-	gui.write_screen(&mut state.msg_buff);
-	
-    'mainloop: loop {
-		//let mut m = npcs.get(&(17, 17)).unwrap().borrow_mut();
-		//let initiative_order = vec![m];
 
+	(world, state)
+}
+
+// Move the player along the staircase they're standing on to wherever it
+// comes out
+fn take_stairs(world: &World, state: &mut GameState) {
+	let here = (state.level, state.player.row, state.player.col);
+	let dest = match world.stairs_dest(here) {
+		Some(dest) => dest,
+		None => return,
+	};
+
+	if world.levels[dest.0].npcs.contains_key(&(dest.1, dest.2)) {
+		state.write_msg_buff("Something is blocking the stairs.");
+		return;
+	}
+
+	if dest.0 > state.level {
+		state.write_msg_buff("You climb down into the dark.");
+	} else {
+		state.write_msg_buff("You climb back up.");
+	}
+	state.level = dest.0;
+	state.player.row = dest.1;
+	state.player.col = dest.2;
+}
+
+// Play a game until the player dies or quits, switching levels whenever they
+// take the stairs. Quitting saves the game to save_file (if there is one) so
+// it can be picked up again next time.
+fn play_game(world: &mut World, state: &mut GameState, gui: &mut dyn Frontend,
+		save_file: Option<&Path>) -> Outcome {
+	let result = loop {
+		let result = main_loop(&mut world.levels[state.level], state, gui);
+		if result != Outcome::Stairs {
+			break result;
+		}
+
+		take_stairs(world, state);
+	};
+
+	match result {
+		Outcome::Died => show_game_over(state, gui),
+		Outcome::Quit => {
+			if let Some(path) = save_file {
+				if let Err(msg) = save::save_game(path, world, state) {
+					gui.write_long_msg(&[msg], false);
+				}
+			}
+		},
+		Outcome::Stairs => { },
+	}
+
+	result
+}
+
+// Pick a saved game up where it was left off. The save file is removed once
+// it's loaded so a dead character stays dead.
+fn load_saved_game(gui: &mut dyn Frontend, saved_game: &str, seed: u64,
+		save_file: Option<&Path>) -> Option<save::Game> {
+	match save::read_game(saved_game, seed) {
+		Ok(mut game) => {
+			if let Some(path) = save_file {
+				let _ = fs::remove_file(path);
+			}
+			let name = game.1.player.name.clone();
+			game.1.write_msg_buff(&format!("Welcome back, {}!", name));
+			Some(game)
+		},
+		Err(msg) => {
+			gui.write_long_msg(&[msg, String::from(""),
+				String::from("Starting a new game instead.")], false);
+			None
+		}
+	}
+}
+
+fn run(gui: &mut dyn Frontend, seed: u64, saved_game: Option<&str>, save_file: Option<&Path>) {
+	let loaded = saved_game.and_then(|s| load_saved_game(gui, s, seed, save_file));
+	let (player_name, (mut world, mut state)) = match loaded {
+		Some(game) => (game.1.player.name.clone(), game),
+		None => {
+			show_intro(gui);
+			let player_name = gui.query_user("Who are you?");
+			let game = new_game(&player_name, seed);
+			(player_name, game)
+		}
+	};
+
+	// Games after the first get their seeds from this so that a whole
+	// session can be replayed from the one seed
+	let mut seeds = GameRng::seed_from_u64(seed);
+
+	let mut result = play_game(&mut world, &mut state, gui, save_file);
+	while result == Outcome::Died {
+		match gui.query_single_response("Play again? (y/n)") {
+			Some('y') => {
+				let (mut world, mut state) = new_game(&player_name, seeds.gen());
+				result = play_game(&mut world, &mut state, gui, save_file);
+			},
+			_ => break,
+		}
+	}
+}
+
+// Where in dir to record a game started from seed. Recordings are named after the
+// seed so that starting a new game doesn't wipe out the last one (which might
+// be the only way to reproduce a bug), and numbered if that seed has been
+// recorded before.
+fn replay_path(dir: &Path, seed: u64) -> PathBuf {
+	let mut path = dir.join(format!("game-{}.replay", seed));
+	let mut n = 2;
+	while path.exists() {
+		path = dir.join(format!("game-{}.{}.replay", seed, n));
+		n += 1;
+	}
+
+	path
+}
+
+// How a session gets played: live, with everything the player does recorded
+// to the given file, or played back from an earlier recording with a pause
+// between commands.
+enum Mode {
+	Play(u64, PathBuf),
+	Replay(replay::Replay, Duration),
+}
+
+// Run a session in the given mode. A replay returns how it went, to be
+// shown once the frontend has been put away.
+fn start(gui: &mut dyn Frontend, mode: &Mode) -> Option<String> {
+	match mode {
+		Mode::Play(seed, replay_file) => {
+			let saved_game = fs::read_to_string(SAVE_FILE).ok();
+			let save_file = Some(Path::new(SAVE_FILE));
+			match File::create(replay_file) {
+				Ok(file) => {
+					let mut rec = replay::Recorder::new(gui, file, *seed, saved_game.as_deref());
+					run(&mut rec, *seed, saved_game.as_deref(), save_file);
+				},
+				Err(e) => {
+					gui.write_long_msg(&[format!("Error creating {}: {}", replay_file.display(), e), String::from(""),
+						String::from("This game won't be recorded.")], false);
+					run(gui, *seed, saved_game.as_deref(), save_file);
+				}
+			}
+
+			None
+		},
+		Mode::Replay(replay, delay) => {
+			// The replay brings its own saved game, and the real one is left alone
+			let mut rp = replay::Replayer::new(gui, replay, *delay);
+			run(&mut rp, replay.seed, replay.saved_game.as_deref(), None);
+
+			Some(match rp.error {
+				Some(msg) => msg,
+				None => format!("Replay finished after {} inputs.", rp.played),
+			})
+		}
+	}
+}
+
+// Advance the clock until the player has enough energy to act again, giving
+// every NPC its turns along the way. NPCs go in order of their position so
+// that the same game always plays out the same way.
+fn run_until_player_ready(map: &Map, state: &mut GameState, npcs: &mut NPCTable) {
+	// The player can't move while the NPCs are taking their turns, so one
+	// pair of maps does for every NPC
+	let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+	let to_player = DijkstraMap::new(map, &[(state.player.row, state.player.col)], &cost);
+	state.from_player = Some(to_player.flee(map, &cost));
+	state.to_player = Some(to_player);
+
+	while state.player.energy < ACTION_COST && state.player.hp > 0 {
+		state.turn += 1;
+		state.player.energy += state.player.speed;
+
+		let mut locs = npcs.keys().copied().collect::<Vec<(usize, usize)>>();
+		locs.sort();
+		let actors = locs.iter()
+			.map(|loc| Rc::clone(&npcs[loc]))
+			.collect::<Vec<Rc<RefCell<dyn Act>>>>();
+
+		for actor in actors {
+			let energy = actor.borrow().get_energy() + actor.borrow().get_speed();
+			actor.borrow_mut().set_energy(energy);
+
+			// No sense carrying on once the player is dead
+			if state.player.hp == 0 {
+				return;
+			}
+
+			while actor.borrow().get_energy() >= ACTION_COST && state.player.hp > 0 {
+				let action = actor.borrow_mut().act(state, map, npcs);
+				match action {
+					Action::Wait => { },
+					Action::Move(r, c) => {
+						let loc = actor.borrow().get_loc();
+						actor::move_npc(map, npcs, state, loc, (r, c));
+					},
+					Action::AttackPlayer => combat::npc_attacks(state, &*actor.borrow()),
+				}
+
+				let energy = actor.borrow().get_energy() - ACTION_COST;
+				actor.borrow_mut().set_energy(energy);
+			}
+		}
+	}
+}
+
+// Runs the game from the current state until the player quits, dies or takes
+// the stairs off the level. Split out from run() so that tests can set up a
+// game by hand and drive it with a HeadlessUI.
+fn main_loop(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend) -> Outcome {
+	draw(level, state, gui);
+	
+    loop {
 		let mut update = false;
+		let mut took_turn = false;
 		let cmd = gui.get_command();
 		match cmd {
-			Cmd::Exit => break 'mainloop,
+			Cmd::Exit => return Outcome::Quit,
 			Cmd::MoveW => {
-				do_move(&map, &mut state, &npcs, &items, "W");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "W");
 				update = true;
 			},
 			Cmd::MoveS => {
-				do_move(&map, &mut state, &npcs, &items, "S");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "S");
 				update = true;
 			},
 			Cmd::MoveN => {
-				do_move(&map, &mut state, &npcs, &items, "N");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "N");
 				update = true;
 			},
 			Cmd::MoveE => {
-				do_move(&map, &mut state, &npcs, &items, "E");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "E");
 				update = true;
 			},
 			Cmd::MoveNW => {
-				do_move(&map, &mut state, &npcs, &items, "NW");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "NW");
 				update = true;
 			},
 			Cmd::MoveNE => {
-				do_move(&map, &mut state, &npcs, &items, "NE");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "NE");
 				update = true;
 			},
 			Cmd::MoveSW => {
-				do_move(&map, &mut state, &npcs, &items, "SW");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "SW");
 				update = true;
 			},
 			Cmd::MoveSE => {
-				do_move(&map, &mut state, &npcs, &items, "SE");
+				took_turn = do_move(&level.map, state, &mut level.npcs, &level.items, "SE");
 				update = true;
 			},
 			Cmd::MsgHistory => {
-				show_message_history(&state, &mut gui);
+				show_message_history(state, gui);
 				update = true;
 			},
 			Cmd::DropItem => {
-				drop_item(&mut state, &mut items, &mut gui);
+				took_turn = drop_item(state, &mut level.items, gui);
 				update = true;
 			}
 			Cmd::PickUp => {
-				pick_up(&mut state, &mut items, &mut gui);
+				took_turn = pick_up(state, &mut level.items, gui);
 				update = true;
 			}
 			Cmd::ShowInventory => {
-				show_inventory(&mut state, &mut gui);
+				show_inventory(state, gui);
+				update = true;
+			}
+			Cmd::Wield => {
+				took_turn = wield(state, gui);
+				update = true;
+			}
+			Cmd::Run(dir) => {
+				run_in_dir(level, state, gui, dir);
+				update = true;
+			}
+			Cmd::Explore => {
+				explore(level, state, gui);
+				update = true;
+			}
+			Cmd::Look => {
+				look(level, state, gui);
+				update = true;
+			}
+			Cmd::Travel => {
+				if let Some(sq) = pick_square(gui, &|_, _| String::from("Travel where?")) {
+					travel_to_viewport_sq(level, state, gui, sq);
+				}
+				update = true;
+			}
+			Cmd::TravelTo(row, col) => {
+				travel_to_viewport_sq(level, state, gui, (row, col));
 				update = true;
 			}
+			Cmd::Down => {
+				if level.map[(state.player.row, state.player.col)] == map::Tile::StairsDown {
+					return Outcome::Stairs;
+				}
+				state.write_msg_buff("You can't go down here.");
+				update = true;
+			}
+			Cmd::Up => {
+				if level.map[(state.player.row, state.player.col)] == map::Tile::StairsUp {
+					return Outcome::Stairs;
+				}
+				state.write_msg_buff("You can't go up here.");
+				update = true;
+			}
+			Cmd::Redraw => update = true,
         }
-	
-		if update {
-			gui.v_matrix = fov::calc_v_matrix(&map, &npcs, &items,
-				state.player.row, state.player.col, FOV_HEIGHT, FOV_WIDTH);
+
+		if took_turn {
+			state.player.energy -= ACTION_COST;
+			run_until_player_ready(&level.map, state, &mut level.npcs);
+		}
+
+		// Draw the screen one last time so the player can see what
+		// killed them before the game over screen
+		if state.player.hp == 0 {
 			gui.write_screen(&mut state.msg_buff);
+			return Outcome::Died;
+		}
+
+		if update {
+			draw(level, state, gui);
 		}
     }
 }
 
-fn main() {
-	let map = map::generate_island(65);
-	//let map = map::generate_cave(20, 10);
-	//let path = pathfinding::find_path(&map, 4, 4, 9, 9);
-	
-	run(&map);
+// Draw the map around the player and whatever messages are waiting
+fn draw(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend) {
+	let (fov_height, fov_width) = gui.viewport_size();
+	gui.set_v_matrix(fov::calc_v_matrix(level,
+		state.player.row, state.player.col, fov_height, fov_width, FOV_RADIUS));
+	gui.write_screen(&mut state.msg_buff);
+}
+
+// The map square drawn at (row, col) of a viewport of the given size, which
+// is always centred on the player. None if that's off the edge of the map.
+fn viewport_to_map(map: &Map, state: &GameState, viewport: (usize, usize),
+		row: usize, col: usize) -> Option<(usize, usize)> {
+	let (fov_height, fov_width) = viewport;
+	let r = state.player.row as i32 + row as i32 - (fov_height / 2) as i32;
+	let c = state.player.col as i32 + col as i32 - (fov_width / 2) as i32;
+
+	if map.in_bounds(r, c) {
+		Some((r as usize, c as usize))
+	} else {
+		None
+	}
+}
+
+// Let the player move a cursor around the viewport with the movement keys
+// (shifted to move faster) to pick a square. prompt() gives the message to
+// show with the cursor at each viewport square. Returns the square's viewport
+// (row, col), or None if they hit Escape.
+fn pick_square(gui: &mut dyn Frontend, prompt: &dyn Fn(usize, usize) -> String) -> Option<(usize, usize)> {
+	let (fov_height, fov_width) = gui.viewport_size();
+	let mut cursor = (fov_height as i32 / 2, fov_width as i32 / 2);
+
+	let picked = loop {
+		gui.set_cursor(Some((cursor.0 as usize, cursor.1 as usize)));
+		let ch = match gui.query_single_response(&prompt(cursor.0 as usize, cursor.1 as usize)) {
+			Some(ch) => ch,
+			None => break None,
+		};
+
+		let dist = if ch.is_ascii_uppercase() { 8 } else { 1 };
+		let mv = match ch.to_ascii_lowercase() {
+			'k' => (-1, 0),
+			'j' => (1, 0),
+			'h' => (0, -1),
+			'l' => (0, 1),
+			'y' => (-1, -1),
+			'u' => (-1, 1),
+			'b' => (1, -1),
+			'n' => (1, 1),
+			'.' | ',' | '_' | '\n' => break Some((cursor.0 as usize, cursor.1 as usize)),
+			_ => (0, 0),
+		};
+		cursor.0 = (cursor.0 + mv.0 * dist).max(0).min(fov_height as i32 - 1);
+		cursor.1 = (cursor.1 + mv.1 * dist).max(0).min(fov_width as i32 - 1);
+	};
+	gui.set_cursor(None);
+
+	picked
+}
+
+// What the player can tell about a map square: whatever's standing there, the
+// top item of any pile and the terrain. Squares they can't see right now are
+// described from memory, which only has the top thing they saw there.
+fn describe_sq(level: &Level, state: &GameState, visible: &HashSet<(usize, usize)>,
+		loc: (usize, usize)) -> String {
+	let Level { map, npcs, items } = level;
+	if loc == (state.player.row, state.player.col) {
+		return format!("That's you, {}!", state.player.name);
+	}
+	if !map.is_explored(loc.0, loc.1) {
+		return String::from("You don't know what's there.");
+	}
+	if !visible.contains(&loc) {
+		return match map.recall(loc.0, loc.1) {
+			map::Tile::Thing(_, ch) => format!("You remember seeing a '{}' there.", ch),
+			tile => format!("You remember {}.", map::tile_name(tile)),
+		};
+	}
+
+	let mut things = Vec::new();
+	if let Some(npc) = npcs.get(&loc) {
+		things.push(with_article(&npc.borrow().get_name()));
+	}
+
+	let count = items.count_at(loc.0, loc.1);
+	if count > 0 {
+		let mut s = with_article(&items.peek_top(loc.0, loc.1).name);
+		if count > 1 {
+			// The message line isn't very wide, so this is kept short
+			s.push_str(&format!(" (+{})", count - 1));
+		}
+		things.push(s);
+	}
+	things.push(map::tile_name(map[loc]).to_string());
+
+	let s = things.join(", ");
+	let mut chars = s.chars();
+	let first = chars.next().unwrap().to_ascii_uppercase();
+	format!("{}{}.", first, chars.as_str())
+}
+
+// Move a cursor around the viewport with the square under it described on
+// the message line, until the player hits Escape (or picks a square)
+fn look(level: &Level, state: &GameState, gui: &mut dyn Frontend) {
+	let visible = fov::visible_squares(&level.map, state.player.row, state.player.col, FOV_RADIUS);
+	let viewport = gui.viewport_size();
+	let describe = |row: usize, col: usize| {
+		match viewport_to_map(&level.map, state, viewport, row, col) {
+			Some(loc) => describe_sq(level, state, &visible, loc),
+			None => String::from("You don't know what's there."),
+		}
+	};
+
+	pick_square(gui, &describe);
+}
+
+// The compass direction do_move() wants for a step between adjacent squares
+fn dir_between(from: (usize, usize), to: (usize, usize)) -> &'static str {
+	let dr = (to.0 as i32 - from.0 as i32).signum();
+	let dc = (to.1 as i32 - from.1 as i32).signum();
+	match (dr, dc) {
+		(-1, 0) => "N",
+		(1, 0) => "S",
+		(0, -1) => "W",
+		(0, 1) => "E",
+		(-1, -1) => "NW",
+		(-1, 1) => "NE",
+		(1, -1) => "SW",
+		_ => "SE",
+	}
+}
+
+// The NPCs the player can currently see. They're identified by the address of
+// their RefCell rather than their location, since they don't stay put.
+fn npcs_in_view(map: &Map, state: &GameState, npcs: &NPCTable) -> HashSet<usize> {
+	let visible = fov::visible_squares(map, state.player.row, state.player.col, FOV_RADIUS);
+	npcs.iter()
+		.filter(|(loc, _)| visible.contains(loc))
+		.map(|(_, npc)| Rc::as_ptr(npc) as *const u8 as usize)
+		.collect()
+}
+
+fn travel_to_viewport_sq(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend,
+		sq: (usize, usize)) {
+	match viewport_to_map(&level.map, state, gui.viewport_size(), sq.0, sq.1) {
+		Some(dest) => travel(level, state, gui, dest),
+		None => state.write_msg_buff("You don't know how to get there."),
+	}
+}
+
+// Walk the player to dest a step at a time, along the quickest route through
+// squares they've already seen, redrawing as they go. They stop early if a
+// monster comes into view or anything gets written to the message line.
+fn travel(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend, dest: (usize, usize)) {
+	let start = (state.player.row, state.player.col);
+	if start == dest {
+		state.write_msg_buff("You're already there!");
+		return;
+	}
+
+	let map = &level.map;
+	let cost = |r: usize, c: usize| {
+		if map.is_explored(r, c) {
+			pathfinding::walking_cost(map[(r, c)])
+		} else {
+			None
+		}
+	};
+	let path = match pathfinding::find_path(map, &cost, start.0, start.1, dest.0, dest.1) {
+		Some((path, _)) => path,
+		None => {
+			state.write_msg_buff("You don't know how to get there.");
+			return;
+		},
+	};
+
+	let mut in_view = npcs_in_view(&level.map, state, &level.npcs);
+	for step in path.iter().skip(1) {
+		// Travelling shouldn't turn into a fight
+		if level.npcs.contains_key(step) {
+			break;
+		}
+
+		let here = (state.player.row, state.player.col);
+		if !auto_step(level, state, gui, dir_between(here, *step), &mut in_view) {
+			break;
+		}
+	}
+}
+
+// A single step of travelling or running: move, give everyone else their
+// turns and redraw. Returns false if the player ought to stop: they couldn't
+// move or died, something was written to the message line (besides splashing
+// into water) or a monster they hadn't seen (in_view is the ones they had)
+// came into view.
+fn auto_step(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend, dir: &str,
+		in_view: &mut HashSet<usize>) -> bool {
+	if !do_move(&level.map, state, &mut level.npcs, &level.items, dir) {
+		return false;
+	}
+	state.player.energy -= ACTION_COST;
+	run_until_player_ready(&level.map, state, &mut level.npcs);
+	if state.player.hp == 0 {
+		return false;
+	}
+
+	let now_in_view = npcs_in_view(&level.map, state, &level.npcs);
+	let interrupted = state.msg_buff.iter().any(|msg| msg != SPLASH_MSG)
+		|| now_in_view.difference(in_view).next().is_some();
+	*in_view = now_in_view;
+
+	draw(level, state, gui);
+
+	!interrupted
+}
+
+// Wander off towards the nearest square the player hasn't seen yet, over and
+// over until there's nowhere left to explore or something comes up along the
+// way (same as for travelling, plus finding items).
+fn explore(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend) {
+	let mut in_view = npcs_in_view(&level.map, state, &level.npcs);
+
+	loop {
+		let map = &level.map;
+		let cost = |r: usize, c: usize| pathfinding::walking_cost(map[(r, c)]);
+		let unexplored = map.coords()
+			.filter(|&(r, c)| !map.is_explored(r, c) && map::is_passable(map[(r, c)]))
+			.collect::<Vec<(usize, usize)>>();
+
+		let here = (state.player.row, state.player.col);
+		let dm = DijkstraMap::new(map, &unexplored, &cost);
+		if dm.get(here.0, here.1).is_none() {
+			state.write_msg_buff("Done exploring.");
+			break;
+		}
+
+		// Nowhere downhill means someone's standing in the way
+		let step = match dm.downhill(here.0, here.1, &|r, c| !level.npcs.contains_key(&(r, c))) {
+			Some(step) => step,
+			None => break,
+		};
+
+		if !auto_step(level, state, gui, dir_between(here, step), &mut in_view) {
+			break;
+		}
+		if level.items.count_at(state.player.row, state.player.col) > 0 {
+			break;
+		}
+	}
+}
+
+// How many ways onward there are from loc for someone who just arrived from
+// prev: the passable squares around loc that aren't prev or next to it. In a
+// corridor there's only the one, and there are more at a fork or where the
+// corridor opens up.
+fn count_exits(map: &Map, loc: (usize, usize), prev: (usize, usize)) -> usize {
+	let mut exits = 0;
+	for r in -1..2 {
+		for c in -1..2 {
+			let nr = loc.0 as i32 + r;
+			let nc = loc.1 as i32 + c;
+			if (r == 0 && c == 0) || !map.in_bounds(nr, nc) {
+				continue;
+			}
+
+			let near_prev = (nr - prev.0 as i32).abs() <= 1 && (nc - prev.1 as i32).abs() <= 1;
+			if !near_prev && map::is_passable(map[(nr as usize, nc as usize)]) {
+				exits += 1;
+			}
+		}
+	}
+
+	exits
+}
+
+// Keep moving in one direction until something interesting happens: the way
+// ahead is blocked or is a different sort of terrain, there's a pile of items
+// underfoot, the way forks or everything that stops travel.
+fn run_in_dir(level: &mut Level, state: &mut GameState, gui: &mut dyn Frontend, dir: &str) {
+	let mv = get_move_tuple(dir);
+	let mut in_view = npcs_in_view(&level.map, state, &level.npcs);
+	let mut exits = None;
+
+	loop {
+		let map = &level.map;
+		let here = (state.player.row, state.player.col);
+		let next_r = here.0 as i32 + mv.0 as i32;
+		let next_c = here.1 as i32 + mv.1 as i32;
+		if !map.in_bounds(next_r, next_c) || level.npcs.contains_key(&(next_r as usize, next_c as usize)) {
+			break;
+		}
+
+		// The first step goes ahead regardless so that the player gets the
+		// usual message if they can't go that way
+		let next_tile = map[(next_r as usize, next_c as usize)];
+		if exits.is_some() && (!map::is_passable(next_tile) || next_tile != map[here]) {
+			break;
+		}
+
+		if !auto_step(level, state, gui, dir, &mut in_view) {
+			break;
+		}
+
+		let loc = (state.player.row, state.player.col);
+		if level.items.count_at(loc.0, loc.1) > 0 {
+			break;
+		}
+
+		let now_exits = count_exits(&level.map, loc, here);
+		if exits.is_some() && exits != Some(now_exits) {
+			break;
+		}
+		exits = Some(now_exits);
+	}
+}
+
+#[cfg(feature = "sdl")]
+fn run_sdl(fov_height: usize, fov_width: usize, mode: &Mode) -> Option<String> {
+    let ttf_context = sdl2::ttf::init()
+		.expect("Error creating ttf context on start-up!");
+	let font_path: &Path = Path::new("DejaVuSansMono.ttf");
+    let font = ttf_context.load_font(font_path, 24)
+		.expect("Error loading game font!");
+	let sm_font = ttf_context.load_font(font_path, 18)
+		.expect("Error loading small game font!");
+	let mut gui = GameUI::init(&font, &sm_font, fov_height, fov_width)
+		.expect("Error initializing GameUI object.");
+
+	start(&mut gui, mode)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(fov_height: usize, fov_width: usize, mode: &Mode) -> Option<String> {
+	// Built without SDL2, so the terminal is all we've got
+	run_terminal(fov_height, fov_width, mode)
+}
+
+fn run_terminal(fov_height: usize, fov_width: usize, mode: &Mode) -> Option<String> {
+	let mut gui = TerminalUI::init(fov_height, fov_width)
+		.expect("Error initializing the terminal.");
+
+	start(&mut gui, mode)
+}
+
+// Play a replay straight through with nothing drawn to the screen, then
+// print the last screen the game drew
+fn run_headless(fov_height: usize, fov_width: usize, mode: &Mode) -> Option<String> {
+	let mut gui = HeadlessUI::with_viewport("", fov_height, fov_width);
+	let result = start(&mut gui, mode);
+
+	if let Some(frame) = gui.last_frame() {
+		for row in 0..frame.cells.len() {
+			println!("{}", frame.row_text(row));
+		}
+	}
+
+	result
+}
+
+// Parse a viewport size written as COLSxROWS (to match how screen sizes
+// are usually written) and return it as (height, width). Anything much
+// smaller than the minimum doesn't leave room for messages and menus.
+fn parse_viewport(s: &str) -> Option<(usize, usize)> {
+	let mut parts = s.split('x');
+	let width = parts.next()?.parse::<usize>().ok()?;
+	let height = parts.next()?.parse::<usize>().ok()?;
+
+	if parts.next().is_some() || width < MIN_FOV_WIDTH || height < MIN_FOV_HEIGHT {
+		None
+	} else {
+		Some((height, width))
+	}
+}
+
+fn main() {
+	
+	let args: Vec<String> = std::env::args().collect();
+	let (fov_height, fov_width) = match args.iter().position(|a| a == "--view") {
+		Some(j) => args.get(j + 1)
+			.and_then(|v| parse_viewport(v))
+			.expect("--view expects the map viewport size as COLSxROWS, eg. 61x31"),
+		None => (FOV_HEIGHT, FOV_WIDTH),
+	};
+
+	// Print the seed so that a game that goes weird can be started again
+	// exactly the same way with --seed
+	let mode = match args.iter().position(|a| a == "--replay") {
+		Some(j) => {
+			let path = args.get(j + 1)
+				.expect("--replay expects the replay file to play, eg. --replay game-12345.replay (games are recorded to game-SEED.replay in the current directory)");
+			let replay = match replay::load_replay(Path::new(path)) {
+				Ok(replay) => replay,
+				Err(msg) => {
+					eprintln!("{}", msg);
+					std::process::exit(1);
+				}
+			};
+			let delay = match args.iter().position(|a| a == "--speed") {
+				Some(j) => args.get(j + 1)
+					.and_then(|v| v.parse::<u64>().ok())
+					.expect("--speed expects the milliseconds to wait between commands, eg. --speed 250"),
+				None => REPLAY_DELAY,
+			};
+			println!("Replaying {} (seed {})", path, replay.seed);
+
+			Mode::Replay(replay, Duration::from_millis(delay))
+		},
+		None => {
+			let seed = match args.iter().position(|a| a == "--seed") {
+				Some(j) => args.get(j + 1)
+					.and_then(|v| v.parse::<u64>().ok())
+					.expect("--seed expects a number, eg. --seed 12345"),
+				None => rand::thread_rng().gen(),
+			};
+			let replay_file = replay_path(Path::new(""), seed);
+			println!("Seed: {} (recording to {})", seed, replay_file.display());
+
+			Mode::Play(seed, replay_file)
+		}
+	};
+
+	// The SDL window is the default; --term plays in the terminal instead
+	// and --headless (for replays) doesn't draw anything at all.
+	let result = if args.iter().any(|a| a == "--headless") {
+		match mode {
+			Mode::Replay(replay, _) => run_headless(fov_height, fov_width, &Mode::Replay(replay, Duration::from_millis(0))),
+			Mode::Play(..) => {
+				eprintln!("--headless only works with --replay");
+				std::process::exit(1);
+			}
+		}
+	} else if args.iter().any(|a| a == "--term") {
+		run_terminal(fov_height, fov_width, &mode)
+	} else {
+		run_sdl(fov_height, fov_width, &mode)
+	};
+
+	if let Some(msg) = result {
+		println!("{}", msg);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::headless::{self, HeadlessUI};
+
+	fn rum() -> items::Item {
+		items::Item::new("draught of rum", items::ItemType::Drink, 1, true,
+			'!', display::BROWN)
+	}
+
+	fn cutlass() -> items::Item {
+		items::Item::new_weapon("rusty cutlass", 3, 6, '|', display::WHITE)
+	}
+
+	fn test_game() -> (Map, GameState, NPCTable, ItemsTable) {
+		let map = Map::new(20, 20, map::Tile::Grass);
+		let mut state = GameState::new(String::from("Tester"), 1);
+		state.player.row = 10;
+		state.player.col = 10;
+
+		(map, state, HashMap::new(), ItemsTable::new())
+	}
+
+	// The tests mostly keep a level's pieces separately, so this puts them
+	// together into a Level for main_loop() and hands them back afterwards
+	fn run_level(map: &mut Map, state: &mut GameState, npcs: &mut NPCTable, items: &mut ItemsTable,
+			gui: &mut dyn Frontend) -> Outcome {
+		let mut level = Level::new(map.clone());
+		level.npcs = std::mem::take(npcs);
+		level.items = std::mem::replace(items, ItemsTable::new());
+
+		let result = main_loop(&mut level, state, gui);
+		*map = level.map;
+		*npcs = level.npcs;
+		*items = level.items;
+
+		result
+	}
+
+	fn play(script: &str, map: &Map, state: &mut GameState, npcs: &mut NPCTable,
+			items: &mut ItemsTable) -> HeadlessUI {
+		let keys = headless::parse_key_script(script).unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		run_level(&mut map.clone(), state, npcs, items, &mut gui);
+
+		gui
+	}
+
+	fn history_has(state: &GameState, msg: &str) -> bool {
+		state.msg_history.iter().any(|m| m.0 == msg)
+	}
+
+	#[test]
+	fn pick_up_single_item() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		items.add(10, 10, cutlass());
+
+		play(",", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(items.count_at(10, 10), 0);
+		assert_eq!(state.player.inventory.count_in_slot('a'), 1);
+		assert!(history_has(&state, "You pick up the rusty cutlass."));
+	}
+
+	#[test]
+	fn pick_up_nothing() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+
+		play(",", &map, &mut state, &mut npcs, &mut items);
+
+		assert!(history_has(&state, "There is nothing here to pick up."));
+	}
+
+	#[test]
+	fn pick_up_some_of_pile() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		items.add(10, 10, rum());
+		items.add(10, 10, cutlass());
+
+		// The cutlass was added last so it's on top of the pile as option a
+		play(", a <ret>", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(items.count_at(10, 10), 1);
+		assert_eq!(items.peek_top(10, 10).name, "draught of rum");
+		assert_eq!(state.player.inventory.get_menu(), vec!["a) a rusty cutlass"]);
+	}
+
+	#[test]
+	fn escape_from_pick_up_menu() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		items.add(10, 10, rum());
+		items.add(10, 10, cutlass());
+
+		play(", <esc>", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(items.count_at(10, 10), 2);
+		assert!(history_has(&state, "Nevermind."));
+	}
+
+	#[test]
+	fn step_onto_pile() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		items.add(10, 11, rum());
+		items.add(10, 11, cutlass());
+
+		let gui = play("l", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 11));
+		assert!(history_has(&state, "You see a few items here."));
+		let frame = gui.last_frame().unwrap();
+		assert!(frame.row_text(0).starts_with("You see a few items here."));
+	}
+
+	#[test]
+	fn drop_when_empty_handed() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+
+		play("d", &map, &mut state, &mut npcs, &mut items);
+
+		assert!(history_has(&state, "You are empty handed."));
+	}
+
+	#[test]
+	fn drop_single_item() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		state.player.inventory.add(cutlass());
+
+		play("d a", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(items.count_at(10, 10), 1);
+		assert_eq!(state.player.inventory.count_in_slot('a'), 0);
+		assert!(history_has(&state, "You drop the rusty cutlass."));
+	}
+
+	#[test]
+	fn drop_part_of_stack() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		for _ in 0..3 {
+			state.player.inventory.add(rum());
+		}
+
+		play("d a 2 <ret>", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(items.count_at(10, 10), 2);
+		assert_eq!(state.player.inventory.count_in_slot('a'), 1);
+		assert!(history_has(&state, "You drop 2 draughts of rum"));
+	}
+
+	#[test]
+	fn escape_from_drop_count() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		for _ in 0..3 {
+			state.player.inventory.add(rum());
+		}
+
+		play("d a 2 <esc>", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(items.count_at(10, 10), 0);
+		assert_eq!(state.player.inventory.count_in_slot('a'), 3);
+		assert!(history_has(&state, "Nevermind."));
+	}
+
+	#[test]
+	fn dropped_item_returns_to_its_slot() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		state.player.inventory.add(rum());
+		state.player.inventory.add(cutlass());
+
+		play("d a , ", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(state.player.inventory.count_in_slot('a'), 1);
+		assert_eq!(state.player.inventory.get_menu()[0], "a) a draught of rum");
+	}
+
+	#[test]
+	fn script_file() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		for _ in 0..3 {
+			items.add(10, 10, rum());
+		}
+		items.add(10, 10, cutlass());
+
+		let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+			.join("tests/scripts/pick_up_and_drop.keys");
+		let mut gui = HeadlessUI::from_script_file(&path).unwrap();
+		run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+
+		assert_eq!(items.count_at(10, 10), 0);
+		assert_eq!(items.count_at(10, 11), 2);
+		assert_eq!((state.player.row, state.player.col), (10, 11));
+		assert!(history_has(&state, "You drop 2 draughts of rum"));
+	}
+
+	#[test]
+	fn remembers_squares_out_of_sight() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		for r in 0..20 {
+			map[(r, 12)] = map::Tile::Wall;
+		}
+		map[(10, 12)] = map::Tile::Grass;
+		items.add(8, 14, rum());
+
+		// Step into the gap in the wall and back again
+		let gui = play("l l h h", &map, &mut state, &mut npcs, &mut items);
+
+		// The rum is now hidden behind the wall
+		let frame = gui.last_frame().unwrap();
+		let (ch, colour) = frame.cells[FOV_HEIGHT / 2 - 1][FOV_WIDTH / 2 + 4];
+		assert_eq!(ch, '!');
+		assert_eq!(colour, display::BROWN.dimmed());
+	}
+
+	#[test]
+	fn smaller_viewport() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		let mut gui = HeadlessUI::with_viewport("", 11, 21);
+		run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+
+		let frame = gui.last_frame().unwrap();
+		assert_eq!(frame.cells.len(), 12);
+		assert_eq!(frame.cells[0].len(), 29);
+		assert_eq!(frame.cells[6][10].0, '@');
+	}
+
+	#[test]
+	fn replays_dont_overwrite_each_other() {
+		let dir = std::env::temp_dir().join(format!("rl-demo-replays-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+
+		let first = replay_path(&dir, 42);
+		assert_eq!(first, dir.join("game-42.replay"));
+		File::create(&first).unwrap();
+		let second = replay_path(&dir, 42);
+		assert_eq!(second, dir.join("game-42.2.replay"));
+		File::create(&second).unwrap();
+		assert_eq!(replay_path(&dir, 42), dir.join("game-42.3.replay"));
+		assert_eq!(replay_path(&dir, 43), dir.join("game-43.replay"));
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn parse_viewport_sizes() {
+		assert_eq!(parse_viewport("61x31"), Some((31, 61)));
+		assert_eq!(parse_viewport("61x3"), None);
+		assert_eq!(parse_viewport("61"), None);
+		assert_eq!(parse_viewport("61x31x2"), None);
+	}
+
+	// An NPC that just counts how many turns it's been given
+	struct Counter {
+		speed: i32,
+		energy: i32,
+		turns: Rc<RefCell<u32>>,
+	}
+
+	impl Act for Counter {
+		fn act(&mut self, _state: &mut GameState, _map: &Map, _npcs: &NPCTable) -> Action {
+			*self.turns.borrow_mut() += 1;
+			Action::Wait
+		}
+
+		fn get_tile_info(&self) -> (display::Color, char) {
+			(display::WHITE, 'c')
+		}
+
+		fn get_name(&self) -> String {
+			String::from("counter")
+		}
+
+		fn get_ac(&self) -> u8 {
+			10
+		}
+
+		fn get_hp(&self) -> u8 {
+			1
+		}
+
+		fn get_max_hp(&self) -> u8 {
+			1
+		}
+
+		fn set_hp(&mut self, _hp: u8) { }
+
+		fn get_dmg(&self) -> u8 {
+			1
+		}
+
+		fn get_loc(&self) -> (usize, usize) {
+			(0, 0)
+		}
+
+		fn set_loc(&mut self, _row: usize, _col: usize) { }
+
+		fn get_speed(&self) -> i32 {
+			self.speed
+		}
+
+		fn get_energy(&self) -> i32 {
+			self.energy
+		}
+
+		fn set_energy(&mut self, energy: i32) {
+			self.energy = energy;
+		}
+	}
+
+	fn add_counter(npcs: &mut NPCTable, row: usize, col: usize, speed: i32) -> Rc<RefCell<u32>> {
+		let turns = Rc::new(RefCell::new(0));
+		let c = Counter { speed, energy: 0, turns: Rc::clone(&turns) };
+		npcs.insert((row, col), Rc::new(RefCell::new(c)));
+
+		turns
+	}
+
+	#[test]
+	fn npcs_get_turns_when_player_acts() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		map[(9, 10)] = map::Tile::Wall;
+		items.add(10, 10, rum());
+		let normal = add_counter(&mut npcs, 2, 2, actor::NORMAL_SPEED);
+		let fast = add_counter(&mut npcs, 3, 3, actor::NORMAL_SPEED * 2);
+		let slow = add_counter(&mut npcs, 4, 4, actor::NORMAL_SPEED / 2);
+
+		// Picking up and moving take a turn each. Walking into the wall,
+		// looking at the inventory and looking at messages don't.
+		play(", k i <c-h> l l d a", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(state.turn, 4);
+		assert_eq!(*normal.borrow(), 4);
+		assert_eq!(*fast.borrow(), 8);
+		assert_eq!(*slow.borrow(), 2);
+	}
+
+	fn add_orc(npcs: &mut NPCTable, row: usize, col: usize) {
+		let m = actor::Monster::new("orc", 13, 8, 3, 'o', (row, col), display::BLUE);
+		npcs.insert((row, col), Rc::new(RefCell::new(m)));
+	}
+
+	// Put a wall around the edge of the map
+	fn wall_in(map: &mut Map) {
+		let (height, width) = (map.height(), map.width());
+		for r in 0..height {
+			map[(r, 0)] = map::Tile::Wall;
+			map[(r, width - 1)] = map::Tile::Wall;
+		}
+		for c in 0..width {
+			map[(0, c)] = map::Tile::Wall;
+			map[(height - 1, c)] = map::Tile::Wall;
+		}
+	}
+
+	fn distance_to_player(state: &GameState, loc: (usize, usize)) -> usize {
+		let dr = (loc.0 as i32 - state.player.row as i32).abs();
+		let dc = (loc.1 as i32 - state.player.col as i32).abs();
+		dr.max(dc) as usize
+	}
+
+	#[test]
+	fn monsters_chase_player() {
+		let (mut map, mut state, mut npcs, _) = test_game();
+		wall_in(&mut map);
+		add_orc(&mut npcs, 3, 16);
+
+		for turn in 1..7 {
+			state.player.energy = 0;
+			run_until_player_ready(&map, &mut state, &mut npcs);
+
+			let loc = *npcs.keys().next().unwrap();
+			assert_eq!(distance_to_player(&state, loc), 7 - turn);
+		}
+
+		// Now it's close enough to start swinging
+		state.player.energy = 0;
+		run_until_player_ready(&map, &mut state, &mut npcs);
+		assert!(history_has(&state, "The orc hits you!") || history_has(&state, "The orc misses you."));
+	}
+
+	#[test]
+	fn monsters_path_around_npcs() {
+		let (mut map, mut state, mut npcs, _) = test_game();
+		wall_in(&mut map);
+		add_orc(&mut npcs, 10, 4);
+		// Something that won't budge is standing right in the orc's way
+		add_counter(&mut npcs, 10, 5, actor::NORMAL_SPEED);
+
+		for _ in 0..3 {
+			state.player.energy = 0;
+			run_until_player_ready(&map, &mut state, &mut npcs);
+		}
+
+		let orc = npcs.keys().find(|loc| **loc != (10, 5)).unwrap();
+		assert_eq!(distance_to_player(&state, *orc), 3);
+	}
+
+	#[test]
+	fn hurt_monsters_flee() {
+		let (mut map, mut state, mut npcs, _) = test_game();
+		wall_in(&mut map);
+		add_orc(&mut npcs, 10, 12);
+		npcs[&(10, 12)].borrow_mut().set_hp(2);
+
+		for turn in 1..4 {
+			state.player.energy = 0;
+			run_until_player_ready(&map, &mut state, &mut npcs);
+
+			let loc = *npcs.keys().next().unwrap();
+			assert_eq!(distance_to_player(&state, loc), 2 + turn);
+		}
+		assert_eq!(state.msg_history.len(), 0);
+	}
+
+	#[test]
+	fn move_npc_keeps_key_and_loc_in_sync() {
+		let (mut map, state, mut npcs, _) = test_game();
+		map[(5, 6)] = map::Tile::Wall;
+		add_orc(&mut npcs, 5, 5);
+		add_orc(&mut npcs, 6, 6);
+
+		assert!(!actor::move_npc(&map, &mut npcs, &state, (5, 5), (5, 6)));
+		assert!(!actor::move_npc(&map, &mut npcs, &state, (5, 5), (6, 6)));
+		assert!(!actor::move_npc(&map, &mut npcs, &state, (5, 5), (10, 10)));
+		assert!(!actor::move_npc(&map, &mut npcs, &state, (5, 5), (20, 5)));
+		assert!(!actor::move_npc(&map, &mut npcs, &state, (7, 7), (7, 8)));
+		assert_eq!(npcs[&(5, 5)].borrow().get_loc(), (5, 5));
+
+		assert!(actor::move_npc(&map, &mut npcs, &state, (5, 5), (4, 5)));
+		assert!(!npcs.contains_key(&(5, 5)));
+		assert_eq!(npcs[&(4, 5)].borrow().get_loc(), (4, 5));
+	}
+
+	#[test]
+	fn wandering_monsters_stay_in_sync() {
+		let (mut map, mut state, mut npcs, _) = test_game();
+		wall_in(&mut map);
+		for j in 0..5 {
+			add_orc(&mut npcs, 3 + j, 3 + j);
+		}
+
+		for _ in 0..50 {
+			state.player.energy = 0;
+			run_until_player_ready(&map, &mut state, &mut npcs);
+		}
+
+		assert_eq!(npcs.len(), 5);
+		for (loc, npc) in npcs.iter() {
+			assert_eq!(*loc, npc.borrow().get_loc());
+			assert!(map::is_passable(map[*loc]));
+			assert_ne!(*loc, (state.player.row, state.player.col));
+		}
+	}
+
+	#[test]
+	fn bump_to_kill() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		// An ac of 0 means every swing hits
+		let m = actor::Monster::new("rat", 0, 1, 1, 'r', (10, 11), display::BROWN);
+		npcs.insert((10, 11), Rc::new(RefCell::new(m)));
+
+		play("l", &map, &mut state, &mut npcs, &mut items);
+
+		assert!(npcs.is_empty());
+		assert_eq!((state.player.row, state.player.col), (10, 10));
+		assert!(history_has(&state, "You kill the rat!"));
+	}
+
+	#[test]
+	fn monsters_attack_adjacent_player() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		let m = actor::Monster::new("rat", 30, 20, 1, 'r', (11, 11), display::BROWN);
+		npcs.insert((11, 11), Rc::new(RefCell::new(m)));
+		state.player.ac = 0;
+
+		// The rat is too well armoured to hit but it can't miss us
+		play("n n n", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(state.player.hp, 7);
+		assert_eq!(npcs.len(), 1);
+		assert!(history_has(&state, "You miss the rat."));
+		assert!(history_has(&state, "The rat hits you!"));
+	}
+
+	#[test]
+	fn player_dies() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		// With an ac of 0 the rat can't miss
+		state.player.ac = 0;
+		state.player.hp = 1;
+		state.player.inventory.add(rum());
+		let m = actor::Monster::new("rat", 30, 20, 1, 'r', (11, 11), display::BROWN);
+		npcs.insert((11, 11), Rc::new(RefCell::new(m)));
+
+		let keys = headless::parse_key_script("n n n").unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		let result = run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+
+		assert_eq!(result, Outcome::Died);
+		assert_eq!(state.turn, 1);
+		assert_eq!(state.killed_by, Some(String::from("rat")));
+		assert!(history_has(&state, "You die..."));
+
+		show_game_over(&state, &mut gui);
+		let frame = gui.last_frame().unwrap();
+		assert_eq!(frame.row_text(1), "Tester was killed by a rat.");
+		assert_eq!(frame.row_text(2), "They survived for 1 turn.");
+		assert_eq!(frame.row_text(4), "They were carrying:");
+		assert_eq!(frame.row_text(5), "a) a draught of rum");
+	}
+
+	#[test]
+	fn game_over_empty_handed() {
+		let (_, mut state, _, _) = test_game();
+		state.turn = 2;
+		let mut gui = HeadlessUI::new("");
+
+		show_game_over(&state, &mut gui);
+		let frame = gui.last_frame().unwrap();
+		assert_eq!(frame.row_text(1), "Tester died.");
+		assert_eq!(frame.row_text(2), "They survived for 2 turns.");
+		assert_eq!(frame.row_text(4), "They died empty-handed.");
+	}
+
+	#[test]
+	fn quitting_isnt_dying() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		let keys = headless::parse_key_script("l Q").unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+
+		let result = run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+
+		assert_eq!(result, Outcome::Quit);
+		assert_eq!(state.killed_by, None);
+	}
+
+	#[test]
+	fn wield_weapon() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		state.player.inventory.add(rum());
+		state.player.inventory.add(cutlass());
+
+		play("w a w b", &map, &mut state, &mut npcs, &mut items);
+
+		assert!(history_has(&state, "That's not a weapon!"));
+		assert!(history_has(&state, "You are now wielding the rusty cutlass."));
+		assert_eq!(state.player.inventory.get_wielded().unwrap().name, "rusty cutlass");
+		assert_eq!(state.player.inventory.get_menu()[1], "b) a rusty cutlass (in hand)");
+		assert_eq!(state.turn, 1);
+
+		play("d b", &map, &mut state, &mut npcs, &mut items);
+		assert!(state.player.inventory.get_wielded().is_none());
+		assert!(!items.peek_top(10, 10).equipped);
+	}
+
+	#[test]
+	fn travel_to_picked_square() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+
+		let gui = play("_ l l j . ", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (11, 12));
+		assert_eq!(state.turn, 2);
+		// The cursor was drawn while picking but is gone afterwards
+		assert!(gui.frames.iter().any(|f| f.cursor == Some((FOV_HEIGHT / 2 + 2, FOV_WIDTH / 2 + 2))));
+		assert_eq!(gui.last_frame().unwrap().cursor, None);
+	}
+
+	#[test]
+	fn travel_stops_for_messages() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		// A corridor so there's only one way to go
+		for c in 0..20 {
+			map[(9, c)] = map::Tile::Wall;
+			map[(11, c)] = map::Tile::Wall;
+		}
+		items.add(10, 13, rum());
+
+		play("_ L .", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 13));
+		assert!(history_has(&state, "You see a draught of rum here."));
+	}
+
+	#[test]
+	fn travel_stops_when_monsters_appear() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		for r in 0..10 {
+			map[(r, 12)] = map::Tile::Wall;
+		}
+		for c in 0..20 {
+			map[(11, c)] = map::Tile::Wall;
+		}
+		for c in 0..12 {
+			map[(9, c)] = map::Tile::Wall;
+		}
+		state.player.col = 3;
+		// Counters never move, so this one only comes into view once the
+		// player is far enough along to see around the wall
+		add_counter(&mut npcs, 3, 15, actor::NORMAL_SPEED);
+		assert!(npcs_in_view(&map, &state, &npcs).is_empty());
+
+		play("_ L l l l l l .", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!(state.player.row, 10);
+		assert!(state.player.col > 3 && state.player.col < 16);
+		assert_eq!(npcs_in_view(&map, &state, &npcs).len(), 1);
+		state.player.col -= 1;
+		assert!(npcs_in_view(&map, &state, &npcs).is_empty());
+	}
+
+	#[test]
+	fn cant_travel_off_the_map() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+
+		play("_ L L .", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 10));
+		assert!(history_has(&state, "You don't know how to get there."));
+		assert_eq!(state.turn, 0);
+	}
+
+	#[test]
+	fn escape_from_travel() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+
+		let gui = play("_ l l <esc>", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 10));
+		assert_eq!(gui.last_frame().unwrap().cursor, None);
+	}
+
+	#[test]
+	fn run_until_blocked() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		wall_in(&mut map);
+
+		play("L", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 18));
+		assert_eq!(state.turn, 8);
+		assert_eq!(state.msg_history.len(), 0);
+	}
+
+	#[test]
+	fn run_stops_at_terrain_change() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		for r in 0..20 {
+			map[(r, 14)] = map::Tile::Water;
+		}
+
+		play("L", &map, &mut state, &mut npcs, &mut items);
+		assert_eq!((state.player.row, state.player.col), (10, 13));
+
+		// Running out of the water is a change too, but the first step
+		// always goes ahead
+		play("l L", &map, &mut state, &mut npcs, &mut items);
+		assert_eq!((state.player.row, state.player.col), (10, 19));
+	}
+
+	#[test]
+	fn run_through_water() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		for r in 8..13 {
+			for c in 0..20 {
+				map[(r, c)] = map::Tile::Water;
+			}
+		}
+		state.player.col = 2;
+
+		play("L", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 19));
+		assert_eq!(state.turn, 17);
+	}
+
+	#[test]
+	fn run_stops_at_items_and_monsters() {
+		let (map, mut state, mut npcs, mut items) = test_game();
+		items.add(6, 6, rum());
+		add_counter(&mut npcs, 10, 16, actor::NORMAL_SPEED);
+
+		play("Y", &map, &mut state, &mut npcs, &mut items);
+		assert_eq!((state.player.row, state.player.col), (6, 6));
+
+		// Running doesn't start fights either
+		state.player.row = 10;
+		state.player.col = 10;
+		play("L L", &map, &mut state, &mut npcs, &mut items);
+		assert_eq!((state.player.row, state.player.col), (10, 15));
+		assert!(!history_has(&state, "You miss the counter."));
+	}
+
+	#[test]
+	fn run_stops_at_forks() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		// A corridor along row 10 with a branch heading north from column 12
+		for r in 0..20 {
+			for c in 0..20 {
+				map[(r, c)] = map::Tile::Wall;
+			}
+		}
+		for c in 2..18 {
+			map[(10, c)] = map::Tile::StoneFloor;
+		}
+		for r in 5..10 {
+			map[(r, 12)] = map::Tile::StoneFloor;
+		}
+		state.player.col = 3;
+
+		play("L", &map, &mut state, &mut npcs, &mut items);
+
+		assert_eq!((state.player.row, state.player.col), (10, 11));
+	}
+
+	// Two rooms joined by a long, bendy corridor so that the far room can't
+	// be seen from the near one
+	fn two_rooms() -> Map {
+		let mut map = Map::new(20, 20, map::Tile::Wall);
+		for r in 2..6 {
+			for c in 2..6 {
+				map[(r, c)] = map::Tile::StoneFloor;
+				map[(r + 12, c + 12)] = map::Tile::StoneFloor;
+			}
+		}
+		for c in 5..17 {
+			map[(3, c)] = map::Tile::StoneFloor;
+		}
+		for r in 3..15 {
+			map[(r, 16)] = map::Tile::StoneFloor;
+		}
+
+		map
+	}
+
+	fn explore_game(map: &mut Map, state: &mut GameState, npcs: &mut NPCTable, items: &mut ItemsTable,
+			script: &str) {
+		let keys = headless::parse_key_script(script).unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		run_level(map, state, npcs, items, &mut gui);
+	}
+
+	#[test]
+	fn explore_everywhere() {
+		let (_, mut state, mut npcs, mut items) = test_game();
+		let mut map = two_rooms();
+		state.player.row = 3;
+		state.player.col = 3;
+
+		explore_game(&mut map, &mut state, &mut npcs, &mut items, "o");
+
+		assert!(history_has(&state, "Done exploring."));
+		for r in 14..18 {
+			for c in 14..18 {
+				assert!(map.is_explored(r, c));
+			}
+		}
+		assert!(state.turn > 0);
+	}
+
+	#[test]
+	fn explore_through_water() {
+		let (_, mut state, mut npcs, mut items) = test_game();
+		let mut map = two_rooms();
+		for c in 9..12 {
+			map[(3, c)] = map::Tile::Water;
+		}
+		for r in 8..10 {
+			map[(r, 16)] = map::Tile::Water;
+		}
+		state.player.row = 3;
+		state.player.col = 3;
+
+		// Wading through the corridor is slow but it's the only way
+		explore_game(&mut map, &mut state, &mut npcs, &mut items, "o");
+
+		assert!(history_has(&state, "Done exploring."));
+		assert!(map.is_explored(16, 16));
+	}
+
+	#[test]
+	fn explore_stops_for_items() {
+		let (_, mut state, mut npcs, mut items) = test_game();
+		let mut map = two_rooms();
+		state.player.row = 3;
+		state.player.col = 3;
+		items.add(10, 16, rum());
+
+		explore_game(&mut map, &mut state, &mut npcs, &mut items, "o");
+		assert_eq!((state.player.row, state.player.col), (10, 16));
+		assert!(!history_has(&state, "Done exploring."));
+
+		explore_game(&mut map, &mut state, &mut npcs, &mut items, "o");
+		assert!(history_has(&state, "Done exploring."));
+	}
+
+	#[test]
+	fn explore_stops_for_monsters() {
+		let (_, mut state, mut npcs, mut items) = test_game();
+		let mut map = two_rooms();
+		state.player.row = 3;
+		state.player.col = 3;
+		add_counter(&mut npcs, 16, 16, actor::NORMAL_SPEED);
+
+		explore_game(&mut map, &mut state, &mut npcs, &mut items, "o");
+
+		assert!(!history_has(&state, "Done exploring."));
+		assert_eq!(npcs_in_view(&map, &state, &npcs).len(), 1);
+	}
+
+	#[test]
+	fn explore_caves() {
+		let mut map = map::generate_cave(40, 20, &mut GameRng::seed_from_u64(40));
+		let mut state = GameState::new(String::from("Tester"), 1);
+		let (mut npcs, mut items) = (HashMap::new(), ItemsTable::new());
+		let start = map.coords()
+			.find(|loc| map::is_passable(map[*loc]))
+			.unwrap();
+		state.player.row = start.0;
+		state.player.col = start.1;
+
+		explore_game(&mut map, &mut state, &mut npcs, &mut items, "o");
+
+		assert!(history_has(&state, "Done exploring."));
+		for r in 0..20 {
+			for c in 0..40 {
+				if map::is_passable(map[(r, c)]) {
+					assert!(map.is_explored(r, c), "{:?} wasn't explored", (r, c));
+				}
+			}
+		}
+	}
+
+	fn look_at(script: &str, map: &Map, state: &mut GameState, npcs: &mut NPCTable,
+			items: &mut ItemsTable) -> String {
+		let gui = play(script, map, state, npcs, items);
+		// The last frame is the redraw after looking, so the description is
+		// on the one before it
+		gui.frames[gui.frames.len() - 2].row_text(0)
+	}
+
+	#[test]
+	fn look_at_things() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		map[(10, 12)] = map::Tile::Water;
+		items.add(10, 12, rum());
+		items.add(10, 12, rum());
+		items.add(10, 12, cutlass());
+		add_counter(&mut npcs, 9, 10, actor::NORMAL_SPEED);
+		add_orc(&mut npcs, 10, 12);
+
+		assert_eq!(look_at("; <esc>", &map, &mut state, &mut npcs, &mut items),
+			"That's you, Tester!");
+		assert_eq!(look_at("x l <esc>", &map, &mut state, &mut npcs, &mut items),
+			"Grass.");
+		assert_eq!(look_at("; k <esc>", &map, &mut state, &mut npcs, &mut items),
+			"A counter, grass.");
+		assert_eq!(look_at("; l l <esc>", &map, &mut state, &mut npcs, &mut items),
+			"An orc, a rusty cutlass (+2), shallow water.");
+		assert_eq!(state.turn, 0);
+	}
+
+	#[test]
+	fn look_at_remembered_squares() {
+		let (mut map, mut state, mut npcs, mut items) = test_game();
+		for r in 0..20 {
+			map[(r, 12)] = map::Tile::Wall;
+		}
+		items.add(10, 14, rum());
+		map.remember(10, 14, map::Tile::Grass);
+
+		let keys = headless::parse_key_script("; l l l l <esc>").unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+		assert_eq!(gui.frames[gui.frames.len() - 2].row_text(0), "You remember grass.");
+
+		// A pile they did see is remembered by its glyph, even if it's
+		// since been picked up
+		map.remember(11, 14, map::Tile::Thing(display::BROWN, '!'));
+		let keys = headless::parse_key_script("; j l l l l <esc>").unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+		assert_eq!(gui.frames[gui.frames.len() - 2].row_text(0),
+			"You remember seeing a '!' there.");
+
+		let keys = headless::parse_key_script("; l l l l l <esc>").unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		run_level(&mut map, &mut state, &mut npcs, &mut items, &mut gui);
+		assert_eq!(gui.frames[gui.frames.len() - 2].row_text(0),
+			"You don't know what's there.");
+	}
+
+	#[test]
+	fn bad_key_name() {
+		assert!(headless::parse_key_script("d<foo>").is_err());
+		assert!(headless::parse_key_script("d<esc").is_err());
+	}
+
+	#[test]
+	fn same_seed_same_game() {
+		let (world, state) = new_game("Tester", 1234);
+		let (world2, state2) = new_game("Tester", 1234);
+
+		assert_eq!(world.levels.len(), world2.levels.len());
+		for (level, level2) in world.levels.iter().zip(world2.levels.iter()) {
+			assert_eq!(level.map, level2.map);
+			assert_eq!(level.npcs.keys().collect::<Vec<_>>(), level2.npcs.keys().collect::<Vec<_>>());
+			assert_eq!(level.items.get_piles().len(), level2.items.get_piles().len());
+		}
+		assert_eq!(world.get_stairs(), world2.get_stairs());
+		assert_eq!((state.player.row, state.player.col), (state2.player.row, state2.player.col));
+
+		let (world3, _) = new_game("Tester", 4321);
+		assert_ne!(world.levels[0].map, world3.levels[0].map);
+	}
+
+	#[test]
+	fn new_game_has_caves() {
+		let (world, state) = new_game("Tester", 1234);
+
+		assert_eq!(state.level, 0);
+		assert_eq!(world.levels.len(), CAVE_COUNT + 1);
+
+		// Every cave has exactly one way back up to the surface
+		let stairs = world.get_stairs();
+		assert_eq!(stairs.len(), CAVE_COUNT);
+		for (j, (top, bottom)) in stairs.iter().enumerate() {
+			assert_eq!(top.0, 0);
+			assert_eq!(world.levels[0].map[(top.1, top.2)], map::Tile::StairsDown);
+			assert_eq!(world.levels[bottom.0].map[(bottom.1, bottom.2)], map::Tile::StairsUp);
+			assert_eq!(world.stairs_dest(*bottom), Some(*top));
+			assert!(stairs[..j].iter().all(|s| s.1.0 != bottom.0));
+		}
+	}
+
+	#[test]
+	fn monsters_start_out_of_the_way() {
+		for seed in 0..10 {
+			let (world, state) = new_game("Tester", seed);
+			let player = (state.player.row, state.player.col);
+			assert!(!world.levels[0].npcs.contains_key(&player));
+
+			for (top, bottom) in world.get_stairs() {
+				assert!(!world.levels[top.0].npcs.contains_key(&(top.1, top.2)));
+				assert!(!world.levels[bottom.0].npcs.contains_key(&(bottom.1, bottom.2)));
+			}
+		}
+	}
+
+	#[test]
+	fn test_items_stay_on_the_map() {
+		// The only floor is the top left corner, so the extra items have
+		// nowhere to go
+		let mut map = Map::new(3, 3, map::Tile::Wall);
+		map[(0, 0)] = map::Tile::StoneFloor;
+		let mut level = Level::new(map);
+		add_test_item(&mut level, &mut GameRng::seed_from_u64(1));
+
+		assert_eq!(level.items.count_at(0, 0), 2);
+		assert_eq!(level.items.get_piles().len(), 1);
+	}
+
+	// A surface and a cave joined by stairs at (10, 10) on the surface and
+	// (5, 5) in the cave, with a counter on each level
+	fn two_level_world() -> (World, GameState, Rc<RefCell<u32>>, Rc<RefCell<u32>>) {
+		let (map, state, _, _) = test_game();
+		let mut surface = Level::new(map);
+		let up_top = add_counter(&mut surface.npcs, 2, 2, actor::NORMAL_SPEED);
+		let mut cave = Level::new(Map::new(12, 12, map::Tile::StoneFloor));
+		let down_below = add_counter(&mut cave.npcs, 8, 8, actor::NORMAL_SPEED);
+
+		let mut world = World::new();
+		world.add_level(surface);
+		world.add_level(cave);
+		world.add_stairs((0, 10, 10), (1, 5, 5));
+
+		(world, state, up_top, down_below)
+	}
+
+	fn play_world(script: &str, world: &mut World, state: &mut GameState) -> HeadlessUI {
+		let keys = headless::parse_key_script(script).unwrap();
+		let mut gui = HeadlessUI::new(&keys);
+		play_game(world, state, &mut gui, None);
+
+		gui
+	}
+
+	#[test]
+	fn down_the_stairs_and_back() {
+		let (mut world, mut state, up_top, down_below) = two_level_world();
+
+		play_world("> l l", &mut world, &mut state);
+		assert_eq!((state.level, state.player.row, state.player.col), (1, 5, 7));
+		assert!(history_has(&state, "You climb down into the dark."));
+
+		// Only the level the player is on gets any turns
+		assert_eq!(*up_top.borrow(), 0);
+		assert_eq!(*down_below.borrow(), 2);
+
+		play_world("h h <lt> j", &mut world, &mut state);
+		assert_eq!((state.level, state.player.row, state.player.col), (0, 11, 10));
+		assert!(history_has(&state, "You climb back up."));
+		assert_eq!(*up_top.borrow(), 1);
+		assert_eq!(*down_below.borrow(), 4);
+	}
+
+	#[test]
+	fn no_stairs_here() {
+		let (mut world, mut state, _, _) = two_level_world();
+		state.player.col = 11;
+
+		play_world("> <lt>", &mut world, &mut state);
+		assert_eq!(state.level, 0);
+		assert!(history_has(&state, "You can't go down here."));
+		assert!(history_has(&state, "You can't go up here."));
+
+		// Standing on the stairs down doesn't let you go up them
+		state.player.col = 10;
+		play_world("<lt>", &mut world, &mut state);
+		assert_eq!(state.level, 0);
+	}
+
+	#[test]
+	fn blocked_stairs() {
+		let (mut world, mut state, _, _) = two_level_world();
+		add_counter(&mut world.levels[1].npcs, 5, 5, actor::NORMAL_SPEED);
+
+		play_world(">", &mut world, &mut state);
+		assert_eq!(state.level, 0);
+		assert!(history_has(&state, "Something is blocking the stairs."));
+	}
+
+	#[test]
+	fn same_seed_same_fight() {
+		let fight = |seed: u64| {
+			let (map, _, mut npcs, mut items) = test_game();
+			let mut state = GameState::new(String::from("Tester"), seed);
+			state.player.row = 10;
+			state.player.col = 10;
+			add_orc(&mut npcs, 11, 11);
+			play("nnnnnn", &map, &mut state, &mut npcs, &mut items);
+
+			state.msg_history
+		};
+
+		assert_eq!(fight(99), fight(99));
+	}
 }
@@ -1,12 +1,13 @@
 extern crate rand;
-extern crate sdl2;
 
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::f32;
+use std::ops::{Index, IndexMut};
 
 use rand::Rng;
-use sdl2::pixels::Color;
+use crate::display::Color;
+use super::GameRng;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Tile {
@@ -22,19 +23,116 @@ pub enum Tile {
 	Mountain,
 	SnowPeak,
 	Gate,
+	StairsDown,
+	StairsUp,
 	StoneFloor,
 	Thing(Color, char), // ie., NPC or item so far
+	Remembered(Color, char), // seen before but not currently in view
 }
 
-// Probably at some point in the dev process, I'll need to begin 
-// storing the map in a struct with extra info instead of just
-// a matrix of Tiles. Then, I won't have to recalculate height and
-// width every time I call the in_bounds() method
-pub fn in_bounds(map: &Vec<Vec<Tile>>, r: i32, c: i32) -> bool {
-	let height = map.len() as i32;
-	let width = map[0].len() as i32;
+// A map level: the terrain, plus layers of whatever else we track per
+// square alongside it. So far that's the player's memory of each square;
+// lighting and so on can go in as more layers. Squares are stored a row at a
+// time and indexed with map[(row, col)], which panics off the edge of the
+// map just like a Vec would, so check in_bounds() first if in doubt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map {
+	height: usize,
+	width: usize,
+	tiles: Vec<Tile>,
+	// The last thing the player saw in each square: the terrain, or the top
+	// item of a pile. Blank means the square has never been seen.
+	memory: Vec<Tile>,
+}
+
+impl Map {
+	pub fn new(height: usize, width: usize, tile: Tile) -> Map {
+		Map { height, width, tiles: vec![tile; height * width], memory: vec![Tile::Blank; height * width] }
+	}
+
+	// Mostly handy for the generators (and tests), which build their maps
+	// up row by row. The rows should all be the same length.
+	pub fn from_rows(rows: Vec<Vec<Tile>>) -> Map {
+		let height = rows.len();
+		let width = if height > 0 { rows[0].len() } else { 0 };
+		let tiles: Vec<Tile> = rows.into_iter().flatten().collect();
+		assert_eq!(tiles.len(), height * width, "Map rows should all be the same width");
+
+		Map { height, width, tiles, memory: vec![Tile::Blank; height * width] }
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	// Signed so that callers can check a step off the top or left edge
+	// without underflowing first
+	pub fn in_bounds(&self, r: i32, c: i32) -> bool {
+		r >= 0 && c >= 0 && (r as usize) < self.height && (c as usize) < self.width
+	}
+
+	// Every square on the map, a row at a time
+	pub fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+		let width = self.width;
+		(0..self.height).flat_map(move |r| (0..width).map(move |c| (r, c)))
+	}
+
+	fn idx(&self, r: usize, c: usize) -> usize {
+		assert!(r < self.height && c < self.width, "({}, {}) is off the map", r, c);
+		r * self.width + c
+	}
+
+	pub fn remember(&mut self, r: usize, c: usize, tile: Tile) {
+		let j = self.idx(r, c);
+		self.memory[j] = tile;
+	}
+
+	pub fn recall(&self, r: usize, c: usize) -> Tile {
+		self.memory[self.idx(r, c)]
+	}
+
+	pub fn is_explored(&self, r: usize, c: usize) -> bool {
+		self.recall(r, c) != Tile::Blank
+	}
+}
+
+impl Index<(usize, usize)> for Map {
+	type Output = Tile;
 
-	r >= 0 && c >= 0 && r < height && c < width
+	fn index(&self, (r, c): (usize, usize)) -> &Tile {
+		&self.tiles[self.idx(r, c)]
+	}
+}
+
+impl IndexMut<(usize, usize)> for Map {
+	fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut Tile {
+		let j = self.idx(r, c);
+		&mut self.tiles[j]
+	}
+}
+
+// How the look command describes each kind of terrain
+pub fn tile_name(tile: Tile) -> &'static str {
+	match tile {
+		Tile::Wall => "a wall",
+		Tile::Tree => "a tree",
+		Tile::Dirt => "dirt",
+		Tile::Grass => "grass",
+		Tile::Water => "shallow water",
+		Tile::DeepWater => "deep water",
+		Tile::Sand => "sand",
+		Tile::StoneFloor => "a stone floor",
+		Tile::Mountain => "a mountain",
+		Tile::SnowPeak => "a snow-capped peak",
+		Tile::Gate => "a gate",
+		Tile::StairsDown => "a staircase down",
+		Tile::StairsUp => "a staircase up",
+		Tile::Blank | Tile::Player | Tile::Thing(_, _) | Tile::Remembered(_, _) => "nothing much",
+	}
 }
 
 pub fn is_clear(tile: Tile) -> bool {
@@ -70,21 +168,21 @@ fn val_to_terrain(val: f32) -> Tile {
 	Tile::SnowPeak
 }
 
-fn fuzz(width: usize, scale: f32) -> f32 {
-	(rand::thread_rng().gen_range(0.0, 1.0) * 2f32 - 1f32) * width as f32 * scale	
+fn fuzz(width: usize, scale: f32, rng: &mut GameRng) -> f32 {
+	(rng.gen_range(0.0, 1.0) * 2f32 - 1f32) * width as f32 * scale	
 }
 
-fn diamond_step(grid: &mut Vec<Vec<f32>>, r: usize, c: usize, width: usize, scale: f32) {
+fn diamond_step(grid: &mut [Vec<f32>], r: usize, c: usize, width: usize, scale: f32, rng: &mut GameRng) {
 	let mut avg = grid[r][c];
 	avg += grid[r][c + width - 1];
 	avg += grid[r + width - 1][c];
 	avg += grid[r + width - 1][c + width - 1];
 	avg /= 4f32;
 
-	grid[r + width /2][c + width / 2] = avg + fuzz(width, scale);
+	grid[r + width /2][c + width / 2] = avg + fuzz(width, scale, rng);
 }
 
-fn calc_diamond_avg(grid: &mut Vec<Vec<f32>>, r: usize, c: usize, width: usize, scale: f32) {
+fn calc_diamond_avg(grid: &mut [Vec<f32>], r: usize, c: usize, width: usize, scale: f32, rng: &mut GameRng) {
 	let mut count = 0;
 	let mut avg = 0.0;
 	if width <= c {
@@ -104,32 +202,32 @@ fn calc_diamond_avg(grid: &mut Vec<Vec<f32>>, r: usize, c: usize, width: usize,
 		count += 1;
 	}
 	
-	grid[r][c] = avg / count as f32 + fuzz(width, scale);
+	grid[r][c] = avg / count as f32 + fuzz(width, scale, rng);
 }
 
-fn square_step(grid: &mut Vec<Vec<f32>>, r: usize, c: usize, width: usize, scale: f32) {
+fn square_step(grid: &mut [Vec<f32>], r: usize, c: usize, width: usize, scale: f32, rng: &mut GameRng) {
 	let half_width = width / 2;
 
-	calc_diamond_avg(grid, r - half_width, c, half_width, scale);
-	calc_diamond_avg(grid, r + half_width, c, half_width, scale);
-	calc_diamond_avg(grid, r, c - half_width, half_width, scale);
-	calc_diamond_avg(grid, r, c + half_width, half_width, scale);
+	calc_diamond_avg(grid, r - half_width, c, half_width, scale, rng);
+	calc_diamond_avg(grid, r + half_width, c, half_width, scale, rng);
+	calc_diamond_avg(grid, r, c - half_width, half_width, scale, rng);
+	calc_diamond_avg(grid, r, c + half_width, half_width, scale, rng);
 }
 
-fn diamond_sq(grid: &mut Vec<Vec<f32>>, r: usize, c: usize, width: usize, scale: f32) {
-	diamond_step(grid, r, c, width, scale);
+fn diamond_sq(grid: &mut Vec<Vec<f32>>, r: usize, c: usize, width: usize, scale: f32, rng: &mut GameRng) {
+	diamond_step(grid, r, c, width, scale, rng);
 	let half_width = width / 2;
-	square_step(grid, r + half_width, c + half_width, width, scale);
+	square_step(grid, r + half_width, c + half_width, width, scale, rng);
 
 	if half_width == 1 {
 		return;
 	}
 
 	let new_scale = scale * 1.95;
-	diamond_sq(grid, r, c, half_width + 1, new_scale);
-	diamond_sq(grid, r, c + half_width, half_width + 1, new_scale);
-	diamond_sq(grid, r + half_width, c, half_width + 1, new_scale);
-	diamond_sq(grid, r + half_width, c + half_width, half_width + 1, new_scale);
+	diamond_sq(grid, r, c, half_width + 1, new_scale, rng);
+	diamond_sq(grid, r, c + half_width, half_width + 1, new_scale, rng);
+	diamond_sq(grid, r + half_width, c, half_width + 1, new_scale, rng);
+	diamond_sq(grid, r + half_width, c + half_width, half_width + 1, new_scale, rng);
 }
 
 fn smooth_map(grid: &mut Vec<Vec<f32>>, width: usize) {
@@ -189,26 +287,22 @@ fn warp_to_island(grid: &mut Vec<Vec<f32>>, width: usize, shift_y: f32) {
 	}
 }
 
-pub fn generate_island(width: usize) -> Vec<Vec<Tile>> {
+pub fn generate_island(width: usize, rng: &mut GameRng) -> Map {
 	let mut grid = vec![vec![0.0f32; width]; width];
 
-	grid[0][0] = rand::thread_rng().gen_range(0.0, 1.0);
-	grid[0][width - 1] = rand::thread_rng().gen_range(0.0, 1.0);
-	grid[width - 1][0] = rand::thread_rng().gen_range(0.0, 1.0);
-	grid[width - 1][width - 1] = rand::thread_rng().gen_range(0.0, 1.0);
+	grid[0][0] = rng.gen_range(0.0, 1.0);
+	grid[0][width - 1] = rng.gen_range(0.0, 1.0);
+	grid[width - 1][0] = rng.gen_range(0.0, 1.0);
+	grid[width - 1][width - 1] = rng.gen_range(0.0, 1.0);
 
 	let initial_scale = 1.0 / width as f32;
-	diamond_sq(&mut grid, 0, 0, width, initial_scale);
+	diamond_sq(&mut grid, 0, 0, width, initial_scale, rng);
 	smooth_map(&mut grid, width);
 	warp_to_island(&mut grid, width, 0.0);
 
-	let mut map: Vec<Vec<Tile>> = Vec::new();
-	for r in 0..width {
-		let mut row = Vec::new();
-		for c in 0..width {
-			row.push(val_to_terrain(grid[r][c]));
-		}
-		map.push(row);
+	let mut map = Map::new(width, width, Tile::Blank);
+	for (r, c) in map.coords() {
+		map[(r, c)] = val_to_terrain(grid[r][c]);
 	}
 
 	map
@@ -365,14 +459,14 @@ fn count_neighbouring_walls(grid: &Vec<Vec<bool>>, row: i32, col: i32, width: i3
 	adj_walls
 }
 
-pub fn generate_cave(width: usize, depth: usize) -> Vec<Vec<Tile>> {
+pub fn generate_cave(width: usize, depth: usize, rng: &mut GameRng) -> Map {
 	let mut grid = vec![vec![true; width]; depth];
 
 	// Set some initial squares to be floors (false indidcates floor in our
 	// initial grid)
 	for r in 0..depth {
 		for c in 0..width {
-			let x: f64 = rand::thread_rng().gen();
+			let x: f64 = rng.gen();
 			if x < 0.55 {
 				grid[r][c] = false;
 			}
@@ -412,19 +506,55 @@ pub fn generate_cave(width: usize, depth: usize) -> Vec<Vec<Tile>> {
 
 	cave_qa(&mut next_gen, width, depth);
 
-	let mut map: Vec<Vec<Tile>> = Vec::new();
-	for r in next_gen {
-		let mut row = Vec::new();
-		for sq in r {
-			let tile = if sq {
-				Tile::Wall
-			} else {
-				Tile::StoneFloor
-			};
-			row.push(tile);
+	let mut map = Map::new(depth, width, Tile::StoneFloor);
+	for (r, c) in map.coords() {
+		if next_gen[r][c] {
+			map[(r, c)] = Tile::Wall;
 		}
-		map.push(row);
 	}
 	
 	map
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	#[should_panic]
+	fn off_the_map_panics() {
+		let map = Map::new(3, 5, Tile::Grass);
+		let _ = map[(3, 0)];
+	}
+
+	#[test]
+	fn map_bounds() {
+		let mut map = Map::new(3, 5, Tile::Grass);
+		assert_eq!((map.height(), map.width()), (3, 5));
+		assert!(map.in_bounds(2, 4));
+		assert!(!map.in_bounds(3, 0));
+		assert!(!map.in_bounds(0, -1));
+
+		map[(2, 4)] = Tile::Wall;
+		assert_eq!(map[(2, 4)], Tile::Wall);
+
+		let coords = map.coords().collect::<Vec<(usize, usize)>>();
+		assert_eq!(coords.len(), 15);
+		assert_eq!((coords[0], coords[5], coords[14]), ((0, 0), (1, 0), (2, 4)));
+	}
+
+	#[test]
+	fn memory_is_its_own_layer() {
+		let mut map = Map::new(3, 5, Tile::Grass);
+		assert!(!map.is_explored(1, 1));
+
+		map.remember(1, 1, Tile::Grass);
+		map.remember(2, 3, Tile::Thing(Color::rgba(1, 2, 3, 255), '!'));
+		assert!(map.is_explored(1, 1));
+		assert_eq!(map.recall(2, 3), Tile::Thing(Color::rgba(1, 2, 3, 255), '!'));
+
+		// Changing the terrain doesn't change what the player remembers
+		map[(1, 1)] = Tile::Wall;
+		assert_eq!(map.recall(1, 1), Tile::Grass);
+	}
+}
@@ -3,20 +3,17 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::cmp::Ordering;
 
-use crate::map;
+use crate::map::{self, Map};
 
 #[derive(Debug)]
 struct ASNode {
-	loc: (usize, usize),
 	parent: (usize, usize),
-	f: usize,
 	g: usize,
-	h: usize,
 }
 
 impl ASNode {
-	fn new(loc: (usize, usize), p: (usize, usize), f: usize, g: usize, h: usize) -> ASNode {
-		ASNode { loc, parent:p, f,g, h }
+	fn new(p: (usize, usize), g: usize) -> ASNode {
+		ASNode { parent:p, g }
 	}
 }
 
@@ -50,8 +47,14 @@ impl PartialEq for ASQueueItem {
     }
 }
 
-fn manhattan_d(ax: usize, ay: usize, bx: usize, by: usize) -> usize {
-	((ax as i32 - bx as i32).abs() + (ay as i32 - by as i32).abs()) as usize	
+// On a grid where diagonal moves cost the same as orthogonal ones, the
+// fewest steps between two squares is the larger of the row and column
+// distances. (Manhattan distance overestimates that, which let A* settle on
+// paths that weren't the shortest.)
+fn chebyshev_d(ar: usize, ac: usize, br: usize, bc: usize) -> usize {
+	let dr = (ar as i32 - br as i32).abs();
+	let dc = (ac as i32 - bc as i32).abs();
+	dr.max(dc) as usize
 }
 
 fn get_path_from_nodes(nodes: &HashMap<(usize, usize), ASNode>,
@@ -71,15 +74,44 @@ fn get_path_from_nodes(nodes: &HashMap<(usize, usize), ASNode>,
 	path.reverse();
 }
 
+// What it costs to step into a square, for creatures getting around on foot.
+// Wading through shallow water or pushing through trees is slow going, and
+// None means the square can't be entered at all.
+pub fn walking_cost(tile: map::Tile) -> Option<usize> {
+	match tile {
+		map::Tile::Water | map::Tile::Tree => Some(2),
+		_ if map::is_passable(tile) => Some(1),
+		_ => None,
+	}
+}
+
+// Like walking_cost() except for creatures that are at home in the water.
+// Nothing swims yet, so for now only the tests use it.
+#[cfg(test)]
+pub fn swimming_cost(tile: map::Tile) -> Option<usize> {
+	match tile {
+		map::Tile::Water | map::Tile::DeepWater => Some(1),
+		_ => walking_cost(tile),
+	}
+}
+
 // I think I could get rid of the redundant data structures with the use
 // of smart pointers (I am keeping a list of visited squares as well as 
 // a hash table of square info to avoid having to fight with the borrow
 // checker and I shouldn't need both). But that'll be for the post-7DRL 
 // future when I have more time.
-pub fn find_path(map: &Vec<Vec<map::Tile>>, start_r: usize, start_c: usize, 
-		end_r: usize, end_c: usize) -> Vec<(usize, usize)> {
+//
+// cost(r, c) is what it costs to step into the square at r, c, or None if
+// it can't be entered. That's where the caller decides about terrain, other
+// creatures in the way and so on. Every step costs at least 1 (anything less
+// is rounded up) so that the distance heuristic never overestimates.
+//
+// Returns the squares along the cheapest path, start and end included, and
+// what the whole trip costs, or None if there's no way to get there.
+pub fn find_path(map: &Map, cost: &dyn Fn(usize, usize) -> Option<usize>,
+		start_r: usize, start_c: usize, end_r: usize, end_c: usize) -> Option<(Vec<(usize, usize)>, usize)> {
 	let mut nodes = HashMap::new();
-	nodes.insert((start_r, start_c), ASNode::new((start_r, start_c), (start_r, start_c), 0, 0, 0));
+	nodes.insert((start_r, start_c), ASNode::new((start_r, start_c), 0));
 	let mut open = BinaryHeap::new();
 	open.push(ASQueueItem::new((start_r, start_c), 0));
 
@@ -89,45 +121,314 @@ pub fn find_path(map: &Vec<Vec<map::Tile>>, start_r: usize, start_c: usize,
 		if current.loc.0 == end_r && current.loc.1 == end_c {
 			let mut path = Vec::new();
 			get_path_from_nodes(&nodes, &mut path, start_r, start_c, end_r, end_c);
-			return path;
+			return Some((path, nodes[&current.loc].g));
 		}
 
-		if !visited.contains(&current.loc) {
-			visited.insert((current.loc.0, current.loc.1));
+		// A square can end up in the queue more than once if a cheaper way
+		// to it turns up after it was first added. Only the first (cheapest)
+		// time it comes off the queue counts.
+		if visited.contains(&current.loc) {
+			continue;
 		}
-		
+		visited.insert(current.loc);
+
 		for r in -1..2 {
 			for c in -1..2 {
 				if r == 0 && c == 0 { continue; }
-	
-				let nr = (current.loc.0 as i32 + r) as usize;
-				let nc = (current.loc.1 as i32 + c) as usize;
-				// note that at the moment this only considers whether
-				// the tile is passable and not say occupied by anotehr 
-				// creature
-				if !map::is_passable(map[nr][nc]) {
+
+				let nr = current.loc.0 as i32 + r;
+				let nc = current.loc.1 as i32 + c;
+				if !map.in_bounds(nr, nc) {
 					continue;
 				}
-	
-				let g = nodes[&current.loc].g + 1;
-				let h = manhattan_d(nr, nc, end_r, end_c);
-				let f = g + h;
-
-				let next = ASNode::new((nr, nc), (current.loc.0, current.loc.1), f, g, h);
-				if !visited.contains(&next.loc) {
-					open.push(ASQueueItem::new((nr, nc), -(f as i32)));
+
+				let loc = (nr as usize, nc as usize);
+				if visited.contains(&loc) {
+					continue;
+				}
+				let step = match cost(loc.0, loc.1) {
+					Some(v) => v.max(1),
+					None => continue,
+				};
+
+				let g = nodes[&current.loc].g + step;
+				if nodes.contains_key(&loc) && nodes[&loc].g <= g {
+					continue;
+				}
+
+				let f = g + chebyshev_d(loc.0, loc.1, end_r, end_c);
+				nodes.insert(loc, ASNode::new(current.loc, g));
+				open.push(ASQueueItem::new(loc, -(f as i32)));
+			}
+		}
+	}
+
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::GameRng;
+	use rand::{Rng, SeedableRng};
+	use std::collections::VecDeque;
+
+	// Plain breadth-first search to check find_path() against. Returns the
+	// number of steps from start to every square reachable from it.
+	fn bfs_distances(map: &Map, start: (usize, usize)) -> HashMap<(usize, usize), usize> {
+		let mut dist = HashMap::new();
+		let mut queue = VecDeque::new();
+		dist.insert(start, 0);
+		queue.push_back(start);
+
+		while let Some(loc) = queue.pop_front() {
+			for r in -1..2 {
+				for c in -1..2 {
+					let nr = loc.0 as i32 + r;
+					let nc = loc.1 as i32 + c;
+					if !map.in_bounds(nr, nc) {
+						continue;
+					}
+					let next = (nr as usize, nc as usize);
+					if map::is_passable(map[next]) && !dist.contains_key(&next) {
+						dist.insert(next, dist[&loc] + 1);
+						queue.push_back(next);
+					}
 				}
+			}
+		}
 
-				if !nodes.contains_key(&next.loc) {
-					nodes.insert((nr, nc), next);
-				} else if g < nodes[&next.loc].g {
-					let n = nodes.get_mut(&next.loc).unwrap();
-					n.g = g;
-					n.parent = (nr, nc);
+		dist
+	}
+
+	fn uniform_cost(map: &Map, r: usize, c: usize) -> Option<usize> {
+		if map::is_passable(map[(r, c)]) {
+			Some(1)
+		} else {
+			None
+		}
+	}
+
+	fn passable_squares(map: &Map) -> Vec<(usize, usize)> {
+		map.coords().filter(|&(r, c)| map::is_passable(map[(r, c)])).collect()
+	}
+
+	fn check_path(map: &Map, path: &[(usize, usize)],
+			start: (usize, usize), end: (usize, usize)) {
+		check_path_with(path, start, end, &|r, c| uniform_cost(map, r, c));
+	}
+
+	// Make sure the path goes from start to end one square at a time, only
+	// through squares that can be entered. Returns what the steps add up to.
+	fn check_path_with(path: &[(usize, usize)], start: (usize, usize), end: (usize, usize),
+			cost: &dyn Fn(usize, usize) -> Option<usize>) -> usize {
+		assert_eq!(path[0], start);
+		assert_eq!(path[path.len() - 1], end);
+		let mut total = 0;
+		for j in 1..path.len() {
+			assert!(chebyshev_d(path[j - 1].0, path[j - 1].1, path[j].0, path[j].1) == 1);
+			total += cost(path[j].0, path[j].1).unwrap();
+		}
+
+		total
+	}
+
+	// Path between a bunch of random pairs of squares and make sure each
+	// path is legal and exactly as long as the shortest one BFS finds
+	fn compare_with_bfs(map: &Map, trials: usize) {
+		let squares = passable_squares(map);
+		let cost = |r: usize, c: usize| uniform_cost(map, r, c);
+		let mut rng = GameRng::seed_from_u64(trials as u64);
+
+		for _ in 0..trials {
+			let start = squares[rng.gen_range(0, squares.len())];
+			let dist = bfs_distances(map, start);
+			for _ in 0..10 {
+				let end = squares[rng.gen_range(0, squares.len())];
+				let result = find_path(map, &cost, start.0, start.1, end.0, end.1);
+
+				match dist.get(&end) {
+					Some(d) => {
+						let (path, total) = result.unwrap();
+						check_path(map, &path, start, end);
+						assert_eq!(path.len() - 1, *d, "{:?} to {:?}", start, end);
+						assert_eq!(total, *d);
+					},
+					None => assert!(result.is_none(), "{:?} to {:?}", start, end),
 				}
 			}
 		}
 	}
 
-	Vec::new()
+	#[test]
+	fn paths_on_islands_are_shortest() {
+		let mut rng = GameRng::seed_from_u64(65);
+		for _ in 0..3 {
+			let map = map::generate_island(65, &mut rng);
+			compare_with_bfs(&map, 20);
+		}
+	}
+
+	#[test]
+	fn paths_in_caves_are_shortest() {
+		let mut rng = GameRng::seed_from_u64(60);
+		for _ in 0..3 {
+			let map = map::generate_cave(60, 30, &mut rng);
+			compare_with_bfs(&map, 20);
+		}
+	}
+
+	#[test]
+	fn paths_along_the_edges() {
+		let map = Map::new(10, 10, map::Tile::Grass);
+		let cost = |r: usize, c: usize| uniform_cost(&map, r, c);
+
+		let (path, total) = find_path(&map, &cost, 0, 0, 9, 9).unwrap();
+		check_path(&map, &path, (0, 0), (9, 9));
+		assert_eq!(total, 9);
+
+		let (path, total) = find_path(&map, &cost, 0, 9, 0, 0).unwrap();
+		check_path(&map, &path, (0, 9), (0, 0));
+		assert_eq!(total, 9);
+
+		let (path, total) = find_path(&map, &cost, 9, 0, 9, 0).unwrap();
+		assert_eq!(path, vec![(9, 0)]);
+		assert_eq!(total, 0);
+	}
+
+	#[test]
+	fn detours_are_shortest() {
+		// A wall with a gap at the far end, so the best route has to double
+		// back, which is where a stale parent used to show up
+		let mut map = Map::new(10, 10, map::Tile::Grass);
+		for r in 0..9 {
+			map[(r, 5)] = map::Tile::Wall;
+		}
+		compare_with_bfs(&map, 20);
+
+		let cost = |r: usize, c: usize| uniform_cost(&map, r, c);
+		let (path, total) = find_path(&map, &cost, 0, 4, 0, 6).unwrap();
+		check_path(&map, &path, (0, 4), (0, 6));
+		assert_eq!(total, 18);
+	}
+
+	#[test]
+	fn no_path_to_unreachable_squares() {
+		let mut map = Map::new(10, 10, map::Tile::Grass);
+		for r in 0..10 {
+			map[(r, 5)] = map::Tile::DeepWater;
+		}
+		let cost = |r: usize, c: usize| walking_cost(map[(r, c)]);
+
+		assert!(find_path(&map, &cost, 2, 2, 2, 8).is_none());
+		assert!(find_path(&map, &cost, 2, 2, 2, 5).is_none());
+
+		// Unless you can swim
+		let cost = |r: usize, c: usize| swimming_cost(map[(r, c)]);
+		let (path, total) = find_path(&map, &cost, 2, 2, 2, 8).unwrap();
+		assert_eq!(check_path_with(&path, (2, 2), (2, 8), &cost), total);
+		assert_eq!(total, 6);
+	}
+
+	#[test]
+	fn occupied_squares_can_be_avoided() {
+		let map = Map::new(3, 10, map::Tile::Grass);
+		let mut occupied = HashSet::new();
+		occupied.insert((0, 5));
+		occupied.insert((1, 5));
+		let cost = |r: usize, c: usize| {
+			if occupied.contains(&(r, c)) { None } else { walking_cost(map[(r, c)]) }
+		};
+
+		let (path, _) = find_path(&map, &cost, 1, 0, 1, 9).unwrap();
+		check_path(&map, &path, (1, 0), (1, 9));
+		assert!(path.contains(&(2, 5)));
+
+		occupied.insert((2, 5));
+		let cost = |r: usize, c: usize| {
+			if occupied.contains(&(r, c)) { None } else { walking_cost(map[(r, c)]) }
+		};
+		assert!(find_path(&map, &cost, 1, 0, 1, 9).is_none());
+	}
+
+	#[test]
+	fn slow_terrain_is_avoided_when_its_cheaper() {
+		// A band of trees across the middle with a gap at one end
+		let mut map = Map::new(10, 10, map::Tile::Grass);
+		for c in 0..10 {
+			map[(5, c)] = map::Tile::Tree;
+		}
+		map[(5, 9)] = map::Tile::Grass;
+		let cost = |r: usize, c: usize| walking_cost(map[(r, c)]);
+
+		// Going through the trees costs 1 extra, going around to the gap is
+		// several steps out of the way
+		let (path, total) = find_path(&map, &cost, 3, 0, 7, 0).unwrap();
+		assert_eq!(check_path_with(&path, (3, 0), (7, 0), &cost), total);
+		assert_eq!(total, 5);
+
+		// But near the gap, it's cheaper to use it
+		let (path, total) = find_path(&map, &cost, 4, 8, 6, 8).unwrap();
+		assert_eq!(check_path_with(&path, (4, 8), (6, 8), &cost), total);
+		assert!(path.contains(&(5, 9)));
+		assert_eq!(total, 2);
+	}
+
+	// Dijkstra's algorithm as the oracle for weighted costs
+	fn cheapest_costs(map: &Map, start: (usize, usize),
+			cost: &dyn Fn(usize, usize) -> Option<usize>) -> HashMap<(usize, usize), usize> {
+		let mut best = HashMap::new();
+		let mut open = BinaryHeap::new();
+		best.insert(start, 0);
+		open.push(std::cmp::Reverse((0, start)));
+
+		while let Some(std::cmp::Reverse((d, loc))) = open.pop() {
+			if d > best[&loc] {
+				continue;
+			}
+			for r in -1..2 {
+				for c in -1..2 {
+					let nr = loc.0 as i32 + r;
+					let nc = loc.1 as i32 + c;
+					if (r == 0 && c == 0) || !map.in_bounds(nr, nc) {
+						continue;
+					}
+					let next = (nr as usize, nc as usize);
+					if let Some(step) = cost(next.0, next.1) {
+						let nd = d + step;
+						if !best.contains_key(&next) || nd < best[&next] {
+							best.insert(next, nd);
+							open.push(std::cmp::Reverse((nd, next)));
+						}
+					}
+				}
+			}
+		}
+
+		best
+	}
+
+	#[test]
+	fn weighted_paths_on_islands_are_cheapest() {
+		let mut rng = GameRng::seed_from_u64(65);
+		let map = map::generate_island(65, &mut rng);
+		let squares = passable_squares(&map);
+		let cost = |r: usize, c: usize| walking_cost(map[(r, c)]);
+
+		for _ in 0..20 {
+			let start = squares[rng.gen_range(0, squares.len())];
+			let best = cheapest_costs(&map, start, &cost);
+			for _ in 0..10 {
+				let end = squares[rng.gen_range(0, squares.len())];
+				match (find_path(&map, &cost, start.0, start.1, end.0, end.1), best.get(&end)) {
+					(Some((path, total)), Some(d)) => {
+						assert_eq!(check_path_with(&path, start, end, &cost), total);
+						assert_eq!(total, *d, "{:?} to {:?}", start, end);
+					},
+					(None, None) => { },
+					(result, d) => panic!("{:?} to {:?}: {:?} vs {:?}", start, end, result, d),
+				}
+			}
+		}
+	}
 }
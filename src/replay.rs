@@ -0,0 +1,585 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::display::Frontend;
+use crate::map;
+use super::{Cmd, MIN_FOV_HEIGHT, MIN_FOV_WIDTH};
+
+// A replay is everything needed to play a session over again exactly: the
+// seed, the saved game it started from (if any), the size of the map
+// viewport and every command and answer the player gave, one per line. The
+// viewport size matters because mouse clicks and the look cursor pick
+// squares by where they are in the viewport, so it's written again whenever
+// it changes. Like saved games, bump REPLAY_VERSION if the layout changes.
+const REPLAY_HEADER: &str = "RustRoguelikeUIDemo replay";
+pub const REPLAY_VERSION: u32 = 2;
+
+pub struct Replay {
+	pub seed: u64,
+	pub saved_game: Option<String>,
+	view: (usize, usize),
+	inputs: VecDeque<String>,
+}
+
+fn cmd_to_str(cmd: Cmd) -> String {
+	let s = match cmd {
+		Cmd::Exit => "Exit",
+		Cmd::MoveN => "MoveN",
+		Cmd::MoveS => "MoveS",
+		Cmd::MoveE => "MoveE",
+		Cmd::MoveW => "MoveW",
+		Cmd::MoveNW => "MoveNW",
+		Cmd::MoveNE => "MoveNE",
+		Cmd::MoveSW => "MoveSW",
+		Cmd::MoveSE => "MoveSE",
+		Cmd::MsgHistory => "MsgHistory",
+		Cmd::PickUp => "PickUp",
+		Cmd::ShowInventory => "ShowInventory",
+		Cmd::DropItem => "DropItem",
+		Cmd::Wield => "Wield",
+		Cmd::Run(dir) => return format!("Run {}", dir),
+		Cmd::Explore => "Explore",
+		Cmd::Look => "Look",
+		Cmd::Travel => "Travel",
+		Cmd::TravelTo(r, c) => return format!("TravelTo {} {}", r, c),
+		Cmd::Redraw => "Redraw",
+		Cmd::Down => "Down",
+		Cmd::Up => "Up",
+	};
+
+	String::from(s)
+}
+
+fn str_to_cmd(s: &str) -> Option<Cmd> {
+	let words = s.split(' ').collect::<Vec<&str>>();
+	let cmd = match words.as_slice() {
+		["Exit"] => Cmd::Exit,
+		["MoveN"] => Cmd::MoveN,
+		["MoveS"] => Cmd::MoveS,
+		["MoveE"] => Cmd::MoveE,
+		["MoveW"] => Cmd::MoveW,
+		["MoveNW"] => Cmd::MoveNW,
+		["MoveNE"] => Cmd::MoveNE,
+		["MoveSW"] => Cmd::MoveSW,
+		["MoveSE"] => Cmd::MoveSE,
+		["MsgHistory"] => Cmd::MsgHistory,
+		["PickUp"] => Cmd::PickUp,
+		["ShowInventory"] => Cmd::ShowInventory,
+		["DropItem"] => Cmd::DropItem,
+		["Wield"] => Cmd::Wield,
+		// Run wants a &'static str so match it against the directions we have
+		["Run", dir] => {
+			let dir = ["N", "S", "E", "W", "NW", "NE", "SW", "SE"].iter().find(|d| *d == dir)?;
+			Cmd::Run(dir)
+		},
+		["Explore"] => Cmd::Explore,
+		["Look"] => Cmd::Look,
+		["Travel"] => Cmd::Travel,
+		["TravelTo", r, c] => Cmd::TravelTo(r.parse().ok()?, c.parse().ok()?),
+		["Redraw"] => Cmd::Redraw,
+		["Down"] => Cmd::Down,
+		["Up"] => Cmd::Up,
+		_ => return None,
+	};
+
+	Some(cmd)
+}
+
+// Keys are written as their character codes so that spaces, newlines and
+// the like survive the trip. Escape is written as esc.
+fn key_to_str(key: Option<char>) -> String {
+	match key {
+		Some(ch) => (ch as u32).to_string(),
+		None => String::from("esc"),
+	}
+}
+
+fn str_to_key(s: &str) -> Option<Option<char>> {
+	if s == "esc" {
+		Some(None)
+	} else {
+		Some(Some(std::char::from_u32(s.parse().ok()?)?))
+	}
+}
+
+fn parse_view(s: &str) -> Result<(usize, usize), String> {
+	let dims = s.split(' ').map(|d| d.parse::<usize>()).collect::<Result<Vec<usize>, _>>();
+	match dims.as_deref() {
+		Ok([h, w]) if *h >= MIN_FOV_HEIGHT && *w >= MIN_FOV_WIDTH => Ok((*h, *w)),
+		_ => Err(format!("Bad viewport size '{}'", s)),
+	}
+}
+
+pub fn load_replay(path: &Path) -> Result<Replay, String> {
+	let s = fs::read_to_string(path)
+		.map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+
+	parse_replay(&s)
+}
+
+fn parse_replay(s: &str) -> Result<Replay, String> {
+	let mut lines = s.lines();
+
+	let header = lines.next().unwrap_or("");
+	if !header.starts_with(REPLAY_HEADER) {
+		return Err(String::from("That isn't a replay file."));
+	}
+	let version = header[REPLAY_HEADER.len()..].trim().parse::<u32>()
+		.map_err(|_| String::from("The replay file's header is garbled."))?;
+	if version != REPLAY_VERSION {
+		return Err(format!("The replay file is version {} but this version of the game can only play version {} replays.",
+			version, REPLAY_VERSION));
+	}
+
+	let seed = match lines.next().and_then(|l| l.strip_prefix("seed ")) {
+		Some(v) => v.parse::<u64>().map_err(|_| format!("Bad seed '{}'", v))?,
+		None => return Err(String::from("The replay file doesn't have a seed.")),
+	};
+
+	let view = match lines.next().and_then(|l| l.strip_prefix("view ")) {
+		Some(v) => parse_view(v)?,
+		None => return Err(String::from("The replay file doesn't have a viewport size.")),
+	};
+
+	let mut saved_game = None;
+	let mut inputs = VecDeque::new();
+	while let Some(line) = lines.next() {
+		if let Some(count) = line.strip_prefix("save ") {
+			let count = count.parse::<usize>().map_err(|_| format!("Bad line count '{}'", count))?;
+			let save = lines.by_ref().take(count).collect::<Vec<&str>>();
+			if save.len() != count {
+				return Err(String::from("The replay file ends in the middle of the saved game."));
+			}
+			saved_game = Some(save.join("\n"));
+		} else if !line.is_empty() {
+			inputs.push_back(String::from(line));
+		}
+	}
+
+	Ok(Replay { seed, saved_game, view, inputs })
+}
+
+// Wraps the real frontend and writes every command and answer the player
+// gives it to a replay file as they happen, so that even a session that ends
+// in a crash leaves a complete recording behind.
+pub struct Recorder<'a> {
+	gui: &'a mut dyn Frontend,
+	file: File,
+	view: (usize, usize),
+}
+
+impl<'a> Recorder<'a> {
+	pub fn new(gui: &'a mut dyn Frontend, file: File, seed: u64, saved_game: Option<&str>) -> Recorder<'a> {
+		let view = gui.viewport_size();
+		let mut rec = Recorder { gui, file, view };
+
+		rec.record(&format!("{} {}", REPLAY_HEADER, REPLAY_VERSION));
+		rec.record(&format!("seed {}", seed));
+		rec.record(&format!("view {} {}", view.0, view.1));
+		if let Some(save) = saved_game {
+			rec.record(&format!("save {}", save.lines().count()));
+			for line in save.lines() {
+				rec.record(line);
+			}
+		}
+
+		rec
+	}
+
+	// If the replay can't be written there's not much to be done about it,
+	// and it's no reason to stop the game
+	fn record(&mut self, line: &str) {
+		let _ = writeln!(self.file, "{}", line);
+	}
+
+	// The window can change size while the frontend is waiting for input, so
+	// after each input check whether the viewport has too. A Redraw always
+	// means the layout changed, so it always gets the size written out.
+	fn record_input(&mut self, line: &str, always_record_view: bool) {
+		self.record(line);
+
+		let view = self.gui.viewport_size();
+		if always_record_view || view != self.view {
+			self.view = view;
+			self.record(&format!("view {} {}", view.0, view.1));
+		}
+	}
+}
+
+impl<'a> Frontend for Recorder<'a> {
+	fn wait_for_key_input(&mut self) -> Option<char> {
+		let key = self.gui.wait_for_key_input();
+		self.record_input(&format!("key {}", key_to_str(key)), false);
+
+		key
+	}
+
+	fn get_command(&mut self) -> Cmd {
+		let cmd = self.gui.get_command();
+		self.record_input(&format!("cmd {}", cmd_to_str(cmd)), cmd == Cmd::Redraw);
+
+		cmd
+	}
+
+	fn viewport_size(&self) -> (usize, usize) {
+		self.gui.viewport_size()
+	}
+
+	fn set_v_matrix(&mut self, v_matrix: Vec<Vec<map::Tile>>) {
+		self.gui.set_v_matrix(v_matrix);
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		self.gui.set_cursor(cursor);
+	}
+
+	fn write_screen(&mut self, msgs: &mut VecDeque<String>) {
+		self.gui.write_screen(msgs);
+	}
+
+	fn write_long_msg(&mut self, lines: &[String], small_text: bool) {
+		self.gui.write_long_msg(lines, small_text);
+	}
+
+	fn draw_menu(&mut self, menu: &[String], answers: &HashSet<u8>) {
+		self.gui.draw_menu(menu, answers);
+	}
+
+	fn query_single_response(&mut self, question: &str) -> Option<char> {
+		let key = self.gui.query_single_response(question);
+		self.record_input(&format!("key {}", key_to_str(key)), false);
+
+		key
+	}
+
+	fn query_natural_num(&mut self, query: &str) -> Option<u8> {
+		let num = self.gui.query_natural_num(query);
+		match num {
+			Some(n) => self.record_input(&format!("num {}", n), false),
+			None => self.record_input("num esc", false),
+		}
+
+		num
+	}
+
+	fn query_user(&mut self, question: &str) -> String {
+		let answer = self.gui.query_user(question);
+		self.record_input(&format!("text {}", answer), false);
+
+		answer
+	}
+
+	fn menu_picker(&mut self, menu: &[String], answer_count: u8) -> Option<HashSet<u8>> {
+		let answers = self.gui.menu_picker(menu, answer_count);
+		match &answers {
+			Some(a) => {
+				let mut picked = a.iter().collect::<Vec<&u8>>();
+				picked.sort();
+				let picked = picked.iter().map(|j| j.to_string()).collect::<Vec<String>>();
+				self.record_input(format!("menu {}", picked.join(" ")).trim_end(), false);
+			},
+			None => self.record_input("menu esc", false),
+		}
+
+		answers
+	}
+}
+
+// Plays a recording back through the real frontend (or a headless one),
+// pausing delay between commands so that it can be watched. Drawing still
+// goes to the frontend, so a --More-- or a long message waits for a real
+// keypress unless the frontend is headless. Once the recording runs out, or
+// stops matching what the game asks for, the replay acts as though the
+// player hit Escape and error says what went wrong. The game is told the
+// viewport is the size it was when the replay was recorded, whatever size
+// the frontend playing it back happens to be.
+pub struct Replayer<'a> {
+	gui: &'a mut dyn Frontend,
+	inputs: VecDeque<String>,
+	view: (usize, usize),
+	delay: Duration,
+	pub played: usize,
+	pub error: Option<String>,
+}
+
+impl<'a> Replayer<'a> {
+	pub fn new(gui: &'a mut dyn Frontend, replay: &Replay, delay: Duration) -> Replayer<'a> {
+		Replayer { gui, inputs: replay.inputs.clone(), view: replay.view, delay, played: 0, error: None }
+	}
+
+	// Viewport sizes are written after the input that came along with the
+	// change, so they take effect once that input has been handed over
+	fn read_views(&mut self) {
+		while let Some(v) = self.inputs.front().and_then(|l| l.strip_prefix("view ")) {
+			match parse_view(v) {
+				Ok(view) => self.view = view,
+				Err(msg) => {
+					self.error = Some(msg);
+					return;
+				}
+			}
+			self.inputs.pop_front();
+		}
+	}
+
+	// The rest of the next input, which should be of the given kind
+	fn next_input(&mut self, kind: &str) -> Option<String> {
+		if self.error.is_some() {
+			return None;
+		}
+
+		let line = self.inputs.pop_front()?;
+		let rest = if line == kind {
+			Some("")
+		} else {
+			line.strip_prefix(kind).and_then(|r| r.strip_prefix(' '))
+		};
+
+		match rest {
+			Some(r) => {
+				let r = String::from(r);
+				self.played += 1;
+				self.read_views();
+				Some(r)
+			},
+			None => {
+				self.error = Some(format!("The replay is out of sync: expected {} but found '{}' (after {} inputs)",
+					kind, line, self.played));
+				None
+			}
+		}
+	}
+
+	fn next_key(&mut self) -> Option<char> {
+		let s = self.next_input("key")?;
+		match str_to_key(&s) {
+			Some(key) => key,
+			None => {
+				self.error = Some(format!("Bad key in replay: {}", s));
+				None
+			}
+		}
+	}
+}
+
+impl<'a> Frontend for Replayer<'a> {
+	fn wait_for_key_input(&mut self) -> Option<char> {
+		self.next_key()
+	}
+
+	fn get_command(&mut self) -> Cmd {
+		thread::sleep(self.delay);
+
+		match self.next_input("cmd") {
+			Some(s) => match str_to_cmd(&s) {
+				Some(cmd) => cmd,
+				None => {
+					self.error = Some(format!("Unknown command in replay: {}", s));
+					Cmd::Exit
+				}
+			},
+			None => Cmd::Exit,
+		}
+	}
+
+	fn viewport_size(&self) -> (usize, usize) {
+		self.view
+	}
+
+	fn set_v_matrix(&mut self, v_matrix: Vec<Vec<map::Tile>>) {
+		self.gui.set_v_matrix(v_matrix);
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		self.gui.set_cursor(cursor);
+	}
+
+	fn write_screen(&mut self, msgs: &mut VecDeque<String>) {
+		self.gui.write_screen(msgs);
+	}
+
+	fn write_long_msg(&mut self, lines: &[String], small_text: bool) {
+		self.gui.write_long_msg(lines, small_text);
+	}
+
+	fn draw_menu(&mut self, menu: &[String], answers: &HashSet<u8>) {
+		self.gui.draw_menu(menu, answers);
+	}
+
+	fn query_single_response(&mut self, question: &str) -> Option<char> {
+		let mut m = VecDeque::new();
+		m.push_front(question.to_string());
+		self.gui.write_screen(&mut m);
+
+		self.next_key()
+	}
+
+	fn query_natural_num(&mut self, _query: &str) -> Option<u8> {
+		let s = self.next_input("num")?;
+		if s == "esc" {
+			return None;
+		}
+
+		match s.parse::<u8>() {
+			Ok(n) => Some(n),
+			Err(_) => {
+				self.error = Some(format!("Bad number in replay: {}", s));
+				None
+			}
+		}
+	}
+
+	fn query_user(&mut self, _question: &str) -> String {
+		self.next_input("text").unwrap_or(String::from(""))
+	}
+
+	fn menu_picker(&mut self, menu: &[String], _answer_count: u8) -> Option<HashSet<u8>> {
+		let s = self.next_input("menu")?;
+		if s == "esc" {
+			return None;
+		}
+
+		let answers = s.split_whitespace().map(|j| j.parse::<u8>()).collect::<Result<HashSet<u8>, _>>();
+		match answers {
+			Ok(a) => {
+				self.gui.draw_menu(menu, &a);
+				Some(a)
+			},
+			Err(_) => {
+				self.error = Some(format!("Bad menu answer in replay: {}", s));
+				None
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::headless::{self, HeadlessUI};
+	use crate::{FOV_HEIGHT, FOV_WIDTH};
+
+	fn temp_path(name: &str) -> std::path::PathBuf {
+		std::env::temp_dir().join(format!("rl-demo-{}-{}.replay", name, std::process::id()))
+	}
+
+	// Play script through a recorder with a viewport of the given size and
+	// return the last screen drawn along with the replay that was written
+	fn record(name: &str, script: &str, seed: u64, view: (usize, usize)) -> (Vec<String>, Replay) {
+		let path = temp_path(name);
+		let keys = headless::parse_key_script(script).unwrap();
+		let mut gui = HeadlessUI::with_viewport(&keys, view.0, view.1);
+		{
+			let mut rec = Recorder::new(&mut gui, File::create(&path).unwrap(), seed, None);
+			crate::run(&mut rec, seed, None, None);
+		}
+		let replay = load_replay(&path);
+		fs::remove_file(&path).unwrap();
+
+		(last_screen(&gui), replay.unwrap())
+	}
+
+	fn last_screen(gui: &HeadlessUI) -> Vec<String> {
+		let frame = gui.last_frame().unwrap();
+		(0..frame.cells.len()).map(|r| frame.row_text(r)).collect()
+	}
+
+	fn play_back(replay: &Replay, view: (usize, usize)) -> (Vec<String>, Option<String>) {
+		let mut gui = HeadlessUI::with_viewport("", view.0, view.1);
+		let error = {
+			let mut rp = Replayer::new(&mut gui, replay, Duration::from_millis(0));
+			crate::run(&mut rp, replay.seed, replay.saved_game.as_deref(), None);
+			rp.error
+		};
+
+		(last_screen(&gui), error)
+	}
+
+	#[test]
+	fn commands_round_trip() {
+		let cmds = [Cmd::Exit, Cmd::MoveNW, Cmd::Run("SE"), Cmd::TravelTo(3, 14), Cmd::Look, Cmd::Redraw];
+		for cmd in cmds.iter() {
+			let s = cmd_to_str(*cmd);
+			assert_eq!(cmd_to_str(str_to_cmd(&s).unwrap()), s);
+		}
+		assert!(str_to_cmd("Run NNW").is_none());
+		assert!(str_to_cmd("Dance").is_none());
+	}
+
+	#[test]
+	fn replay_matches_recording() {
+		let script = "Pip<ret> hhjjkkll yubn L o ; l l . ; l <esc> _ hh . d w i , Q";
+		let (screen, replay) = record("match", script, 77, (FOV_HEIGHT, FOV_WIDTH));
+		assert_eq!(replay.seed, 77);
+		assert_eq!(replay.view, (FOV_HEIGHT, FOV_WIDTH));
+		assert_eq!(replay.inputs.front().unwrap(), "text Pip");
+		assert!(replay.inputs.contains(&String::from("cmd Run E")));
+		assert!(replay.inputs.contains(&String::from("key esc")));
+
+		let (replayed, error) = play_back(&replay, (FOV_HEIGHT, FOV_WIDTH));
+		assert_eq!(error, None);
+		assert_eq!(replayed, screen);
+	}
+
+	#[test]
+	fn replay_keeps_its_viewport_size() {
+		// Shift-moving the cursor runs into the edge of a small viewport much
+		// sooner than a big one, so travelling and looking only go to the same
+		// squares if the replay uses the size it was recorded at
+		let script = "Pip<ret> ; L L J <esc> _ L L J J . _ H Y . ; H H K <esc> Q";
+		let (screen, replay) = record("view", script, 12, (MIN_FOV_HEIGHT, MIN_FOV_WIDTH));
+		assert_eq!(replay.view, (MIN_FOV_HEIGHT, MIN_FOV_WIDTH));
+
+		// The bigger frontend draws the recorded viewport in its top corner
+		let (replayed, error) = play_back(&replay, (FOV_HEIGHT, FOV_WIDTH));
+		assert_eq!(error, None);
+		assert_eq!(replayed[..screen.len()], screen[..]);
+		assert!(replayed[screen.len()..].iter().all(|row| row.is_empty()));
+	}
+
+	#[test]
+	fn viewport_changes_mid_replay() {
+		let s = format!("RustRoguelikeUIDemo replay {}\nseed 9\nview 11 21\ncmd MoveN\ncmd Redraw\nview 15 31\ncmd MoveS\n",
+			REPLAY_VERSION);
+		let replay = parse_replay(&s).unwrap();
+		let mut gui = HeadlessUI::new("");
+		let mut rp = Replayer::new(&mut gui, &replay, Duration::from_millis(0));
+
+		assert_eq!(rp.viewport_size(), (11, 21));
+		rp.get_command();
+		assert_eq!(rp.viewport_size(), (11, 21));
+		assert_eq!(rp.get_command(), Cmd::Redraw);
+		assert_eq!(rp.viewport_size(), (15, 31));
+		assert_eq!(rp.get_command(), Cmd::MoveS);
+		assert_eq!(rp.error, None);
+
+		assert!(parse_replay(&s.replace("view 11 21", "view 3 4")).is_err());
+		assert!(parse_replay(&s.replace("view 11 21\n", "")).is_err());
+	}
+
+	#[test]
+	fn replay_out_of_sync() {
+		let (_, mut replay) = record("sync", "Pip<ret> hjkl Q", 5, (FOV_HEIGHT, FOV_WIDTH));
+		replay.inputs.insert(2, String::from("num 3"));
+
+		let (_, error) = play_back(&replay, (FOV_HEIGHT, FOV_WIDTH));
+		assert!(error.unwrap().starts_with("The replay is out of sync: expected cmd but found 'num 3'"));
+	}
+
+	#[test]
+	fn replays_bring_their_saved_game() {
+		let s = format!("RustRoguelikeUIDemo replay {}\nseed 9\nview 11 21\nsave 2\nRustRoguelikeUIDemo save 1\nturn 3\ncmd MoveN\n",
+			REPLAY_VERSION);
+		let replay = parse_replay(&s).unwrap();
+		assert_eq!(replay.seed, 9);
+		assert_eq!(replay.saved_game.as_deref(), Some("RustRoguelikeUIDemo save 1\nturn 3"));
+		assert_eq!(replay.inputs, vec![String::from("cmd MoveN")]);
+
+		assert!(parse_replay("RustRoguelikeUIDemo replay 99\nseed 9\nview 11 21\n").is_err());
+		assert!(parse_replay(&format!("RustRoguelikeUIDemo replay {}\nsave 2\n", REPLAY_VERSION)).is_err());
+	}
+}
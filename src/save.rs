@@ -0,0 +1,598 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::actor::{Act, Monster};
+use crate::display::Color;
+use crate::items::{Inventory, Item, ItemType};
+use crate::map::Tile;
+use crate::world::{Level, World, WorldLoc};
+use super::{GameState, Map, NPCTable};
+
+// Saved games are plain text, a line per thing, so that they're easy to poke
+// at when something goes wrong. Bump SAVE_VERSION whenever the layout
+// changes so old files get a clear error instead of a garbled game.
+const SAVE_HEADER: &str = "RustRoguelikeUIDemo save";
+pub const SAVE_VERSION: u32 = 2;
+
+pub type Game = (World, GameState);
+
+fn tile_to_code(tile: Tile) -> char {
+	match tile {
+		Tile::Blank => ' ',
+		Tile::Wall => '#',
+		Tile::Tree => 'T',
+		Tile::Dirt => ',',
+		Tile::Grass => '"',
+		Tile::Water => '~',
+		Tile::DeepWater => 'W',
+		Tile::Sand => ':',
+		Tile::StoneFloor => '.',
+		Tile::Mountain => '^',
+		Tile::SnowPeak => 'A',
+		Tile::Gate => '+',
+		Tile::StairsDown => '>',
+		Tile::StairsUp => '<',
+		// Things are saved on a line of their own, see below. The player
+		// and remembered squares never end up in the map or the memory.
+		Tile::Thing(_, _) => '?',
+		Tile::Player | Tile::Remembered(_, _) => ' ',
+	}
+}
+
+fn code_to_tile(ch: char) -> Result<Tile, String> {
+	match ch {
+		' ' => Ok(Tile::Blank),
+		'#' => Ok(Tile::Wall),
+		'T' => Ok(Tile::Tree),
+		',' => Ok(Tile::Dirt),
+		'"' => Ok(Tile::Grass),
+		'~' => Ok(Tile::Water),
+		'W' => Ok(Tile::DeepWater),
+		':' => Ok(Tile::Sand),
+		'.' => Ok(Tile::StoneFloor),
+		'^' => Ok(Tile::Mountain),
+		'A' => Ok(Tile::SnowPeak),
+		'+' => Ok(Tile::Gate),
+		'>' => Ok(Tile::StairsDown),
+		'<' => Ok(Tile::StairsUp),
+		// Filled in by the thing line that goes with it
+		'?' => Ok(Tile::Blank),
+		_ => Err(format!("Unknown map square '{}'", ch)),
+	}
+}
+
+fn color_to_str(c: Color) -> String {
+	format!("{},{},{},{}", c.r, c.g, c.b, c.a)
+}
+
+fn item_type_to_str(t: ItemType) -> &'static str {
+	match t {
+		ItemType::Weapon => "weapon",
+		ItemType::Clothing => "clothing",
+		ItemType::Drink => "drink",
+	}
+}
+
+// Names go last so they can have anything in them
+fn item_to_str(item: &Item) -> String {
+	format!("{}|{}|{}|{}|{}|{}|{}|{}|{}", item_type_to_str(item.item_type), item.weight,
+		item.symbol as u32, color_to_str(item.color), item.stackable, item.prev_slot as u32,
+		item.dmg, item.equipped, item.name)
+}
+
+fn npc_to_str(loc: (usize, usize), npc: &dyn Act) -> String {
+	let (color, symbol) = npc.get_tile_info();
+	format!("{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}", loc.0, loc.1, npc.get_ac(), npc.get_hp(),
+		npc.get_max_hp(), npc.get_dmg(), symbol as u32, color_to_str(color), npc.get_speed(),
+		npc.get_energy(), npc.get_name())
+}
+
+fn write_level(lines: &mut Vec<String>, level: &Level) {
+	let map = &level.map;
+	lines.push(format!("map {} {}", map.height(), map.width()));
+	for r in 0..map.height() {
+		lines.push((0..map.width()).map(|c| tile_to_code(map[(r, c)])).collect());
+	}
+
+	lines.push(format!("memory {} {}", map.height(), map.width()));
+	let mut things = Vec::new();
+	for r in 0..map.height() {
+		let mut s = String::from("");
+		for c in 0..map.width() {
+			let tile = map.recall(r, c);
+			if let Tile::Thing(color, ch) = tile {
+				things.push(format!("thing {}|{}|{}|{}", r, c, ch as u32, color_to_str(color)));
+			}
+			s.push(tile_to_code(tile));
+		}
+		lines.push(s);
+	}
+	lines.extend(things);
+
+	let mut locs = level.npcs.keys().copied().collect::<Vec<(usize, usize)>>();
+	locs.sort();
+	for loc in locs {
+		lines.push(format!("npc {}", npc_to_str(loc, &*level.npcs[&loc].borrow())));
+	}
+
+	for (loc, pile) in level.items.get_piles() {
+		for item in pile {
+			lines.push(format!("item {}|{}|{}", loc.0, loc.1, item_to_str(item)));
+		}
+	}
+}
+
+fn write_game(world: &World, state: &GameState) -> String {
+	let mut lines = vec![format!("{} {}", SAVE_HEADER, SAVE_VERSION)];
+
+	// Each level's map, memory, NPCs and items follow its level line
+	for (j, level) in world.levels.iter().enumerate() {
+		lines.push(format!("level {}", j));
+		write_level(&mut lines, level);
+	}
+	for (top, bottom) in world.get_stairs() {
+		lines.push(format!("stairs {}|{}|{}|{}|{}|{}", top.0, top.1, top.2, bottom.0, bottom.1, bottom.2));
+	}
+
+	let p = &state.player;
+	lines.push(format!("name {}", p.name));
+	lines.push(format!("player {}|{}|{}|{}|{}|{}|{}", p.ac, p.hp, p.row, p.col, p.speed, p.energy,
+		state.level));
+	lines.push(format!("turn {}", state.turn));
+	for (msg, count) in state.msg_history.iter() {
+		lines.push(format!("msg {}|{}", count, msg));
+	}
+
+	lines.push(format!("next_slot {}", p.inventory.get_next_slot() as u32));
+	for (slot, item, count) in p.inventory.get_slots() {
+		lines.push(format!("inv {}|{}|{}", slot as u32, count, item_to_str(item)));
+	}
+
+	let mut s = lines.join("\n");
+	s.push('\n');
+
+	s
+}
+
+pub fn save_game(path: &Path, world: &World, state: &GameState) -> Result<(), String> {
+	fs::write(path, write_game(world, state))
+		.map_err(|e| format!("Error writing {}: {}", path.display(), e))
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+	s.trim().parse::<T>().map_err(|_| format!("Expected a number but found '{}'", s))
+}
+
+fn parse_char(s: &str) -> Result<char, String> {
+	std::char::from_u32(parse_num(s)?).ok_or(format!("Bad character code '{}'", s))
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+	match s {
+		"true" => Ok(true),
+		"false" => Ok(false),
+		_ => Err(format!("Expected true or false but found '{}'", s)),
+	}
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+	let v = s.split(',').map(parse_num).collect::<Result<Vec<u8>, String>>()?;
+	if v.len() != 4 {
+		return Err(format!("Bad colour '{}'", s));
+	}
+
+	Ok(Color::rgba(v[0], v[1], v[2], v[3]))
+}
+
+// Split a line's fields, where the last field gets whatever's left over
+fn fields(s: &str, count: usize) -> Result<Vec<&str>, String> {
+	let v = s.splitn(count, '|').collect::<Vec<&str>>();
+	if v.len() != count {
+		return Err(format!("Expected {} fields in '{}'", count, s));
+	}
+
+	Ok(v)
+}
+
+fn parse_item(s: &str) -> Result<Item, String> {
+	let f = fields(s, 9)?;
+	let item_type = match f[0] {
+		"weapon" => ItemType::Weapon,
+		"clothing" => ItemType::Clothing,
+		"drink" => ItemType::Drink,
+		_ => return Err(format!("Unknown item type '{}'", f[0])),
+	};
+
+	let mut item = Item::new(f[8], item_type, parse_num(f[1])?, parse_bool(f[4])?,
+		parse_char(f[2])?, parse_color(f[3])?);
+	item.prev_slot = parse_char(f[5])?;
+	item.dmg = parse_num(f[6])?;
+	item.equipped = parse_bool(f[7])?;
+
+	Ok(item)
+}
+
+fn parse_npc(s: &str) -> Result<((usize, usize), Monster), String> {
+	let f = fields(s, 11)?;
+	let loc = (parse_num(f[0])?, parse_num(f[1])?);
+	let mut m = Monster::new(f[10], parse_num(f[2])?, parse_num(f[4])?, parse_num(f[5])?,
+		parse_char(f[6])?, loc, parse_color(f[7])?);
+	m.set_hp(parse_num(f[3])?);
+	m.set_speed(parse_num(f[8])?);
+	m.set_energy(parse_num(f[9])?);
+
+	Ok((loc, m))
+}
+
+// Read the rows of squares that follow a "map" or "memory" line
+fn parse_rows(lines: &mut dyn Iterator<Item = &str>, size: &str) -> Result<Vec<Vec<Tile>>, String> {
+	let dims = size.split(' ').map(parse_num).collect::<Result<Vec<usize>, String>>()?;
+	if dims.len() != 2 || dims[0] == 0 || dims[1] == 0 {
+		return Err(format!("Bad map size '{}'", size));
+	}
+
+	let mut rows = Vec::new();
+	for _ in 0..dims[0] {
+		let line = lines.next().ok_or("The save file ends in the middle of the map")?;
+		let row = line.chars().map(code_to_tile).collect::<Result<Vec<Tile>, String>>()?;
+		if row.len() != dims[1] {
+			return Err(format!("Expected a map row {} squares wide", dims[1]));
+		}
+		rows.push(row);
+	}
+
+	Ok(rows)
+}
+
+// A level as it's read in, before we know it's all there
+struct LevelParts {
+	map: Option<Map>,
+	memory_rows: Vec<Vec<Tile>>,
+	npcs: NPCTable,
+	piles: HashMap<(usize, usize), Vec<Item>>,
+}
+
+impl LevelParts {
+	fn new() -> LevelParts {
+		LevelParts { map: None, memory_rows: Vec::new(), npcs: HashMap::new(), piles: HashMap::new() }
+	}
+
+	fn into_level(self, j: usize) -> Result<Level, String> {
+		let mut map = self.map.ok_or(format!("Level {} doesn't have a map.", j))?;
+		if map.height() == 0 || map.width() == 0 {
+			return Err(format!("Level {}'s map is empty.", j));
+		}
+		if self.memory_rows.len() != map.height() || self.memory_rows[0].len() != map.width() {
+			return Err(format!("Level {}'s memory doesn't match its map.", j));
+		}
+
+		// Anything off the edge of the map would blow up the first time the
+		// game went to look at it
+		let on_map = |loc: &(usize, usize)| map.in_bounds(loc.0 as i32, loc.1 as i32);
+		if let Some(loc) = self.npcs.keys().find(|loc| !on_map(loc)) {
+			return Err(format!("There's an NPC off the map at {}, {} on level {}.", loc.0, loc.1, j));
+		}
+		if let Some(loc) = self.piles.keys().find(|loc| !on_map(loc)) {
+			return Err(format!("There are items off the map at {}, {} on level {}.", loc.0, loc.1, j));
+		}
+
+		for (r, row) in self.memory_rows.into_iter().enumerate() {
+			for (c, tile) in row.into_iter().enumerate() {
+				map.remember(r, c, tile);
+			}
+		}
+
+		let mut level = Level::new(map);
+		level.npcs = self.npcs;
+
+		// Piles were saved top down, and adding puts things on top
+		for (loc, pile) in self.piles {
+			for item in pile.into_iter().rev() {
+				level.items.add(loc.0, loc.1, item);
+			}
+		}
+
+		Ok(level)
+	}
+}
+
+fn parse_world_loc(f: &[&str]) -> Result<WorldLoc, String> {
+	Ok((parse_num(f[0])?, parse_num(f[1])?, parse_num(f[2])?))
+}
+
+// The random number generator's state isn't saved, so a restored game
+// carries on with a fresh one seeded from seed
+pub fn read_game(s: &str, seed: u64) -> Result<Game, String> {
+	let mut lines = s.lines();
+
+	let header = lines.next().unwrap_or("");
+	if !header.starts_with(SAVE_HEADER) {
+		return Err(String::from("That isn't a saved game."));
+	}
+	let version: u32 = parse_num(&header[SAVE_HEADER.len()..])?;
+	if version != SAVE_VERSION {
+		return Err(format!("The save file is version {} but this version of the game can only load version {} saves.",
+			version, SAVE_VERSION));
+	}
+
+	let mut levels: Vec<LevelParts> = Vec::new();
+	let mut stairs = Vec::new();
+	let mut state = GameState::new(String::from(""), seed);
+	let mut next_slot = 'a';
+	let mut slots = Vec::new();
+	let mut history = VecDeque::new();
+
+	while let Some(line) = lines.next() {
+		if line.is_empty() {
+			continue;
+		}
+		let (key, rest) = match line.find(' ') {
+			Some(j) => (&line[..j], &line[j + 1..]),
+			None => (line, ""),
+		};
+
+		// Map squares, NPCs and items all belong to the last level line
+		if ["map", "memory", "thing", "npc", "item"].contains(&key) && levels.is_empty() {
+			return Err(format!("Found '{}' before any level", key));
+		}
+		let current = levels.len().saturating_sub(1);
+
+		match key {
+			"level" => levels.push(LevelParts::new()),
+			"map" => levels[current].map = Some(Map::from_rows(parse_rows(&mut lines, rest)?)),
+			"memory" => levels[current].memory_rows = parse_rows(&mut lines, rest)?,
+			"thing" => {
+				let f = fields(rest, 4)?;
+				let (r, c): (usize, usize) = (parse_num(f[0])?, parse_num(f[1])?);
+				let memory_rows = &mut levels[current].memory_rows;
+				if r >= memory_rows.len() || c >= memory_rows[0].len() {
+					return Err(format!("Remembered thing off the map at {}, {}", r, c));
+				}
+				memory_rows[r][c] = Tile::Thing(parse_color(f[3])?, parse_char(f[2])?);
+			},
+			"stairs" => {
+				let f = fields(rest, 6)?;
+				stairs.push((parse_world_loc(&f[..3])?, parse_world_loc(&f[3..])?));
+			},
+			"name" => state.player.name = String::from(rest),
+			"player" => {
+				let f = fields(rest, 7)?;
+				state.player.ac = parse_num(f[0])?;
+				state.player.hp = parse_num(f[1])?;
+				state.player.row = parse_num(f[2])?;
+				state.player.col = parse_num(f[3])?;
+				state.player.speed = parse_num(f[4])?;
+				state.player.energy = parse_num(f[5])?;
+				state.level = parse_num(f[6])?;
+			},
+			"turn" => state.turn = parse_num(rest)?,
+			"msg" => {
+				let f = fields(rest, 2)?;
+				history.push_back((String::from(f[1]), parse_num(f[0])?));
+			},
+			"next_slot" => next_slot = parse_char(rest)?,
+			"inv" => {
+				let f = fields(rest, 3)?;
+				slots.push((parse_char(f[0])?, parse_item(f[2])?, parse_num(f[1])?));
+			},
+			"npc" => {
+				let (loc, m) = parse_npc(rest)?;
+				levels[current].npcs.insert(loc, Rc::new(RefCell::new(m)));
+			},
+			"item" => {
+				let f = fields(rest, 3)?;
+				let loc = (parse_num(f[0])?, parse_num(f[1])?);
+				levels[current].piles.entry(loc).or_default().push(parse_item(f[2])?);
+			},
+			_ => return Err(format!("Unexpected line in save file: {}", line)),
+		}
+	}
+
+	if levels.is_empty() {
+		return Err(String::from("The save file doesn't have any levels."));
+	}
+
+	let mut world = World::new();
+	for (j, parts) in levels.into_iter().enumerate() {
+		world.add_level(parts.into_level(j)?);
+	}
+
+	let on_map = |loc: WorldLoc| loc.0 < world.levels.len()
+		&& world.levels[loc.0].map.in_bounds(loc.1 as i32, loc.2 as i32);
+	if !on_map((state.level, state.player.row, state.player.col)) {
+		return Err(String::from("The player is off the map."));
+	}
+	if world.levels[state.level].npcs.contains_key(&(state.player.row, state.player.col)) {
+		return Err(String::from("There's an NPC standing on the player."));
+	}
+	if stairs.iter().any(|(top, bottom)| !on_map(*top) || !on_map(*bottom)) {
+		return Err(String::from("There are stairs off the map."));
+	}
+	for (top, bottom) in stairs {
+		world.add_stairs(top, bottom);
+	}
+
+	state.msg_history = history;
+	state.player.inventory = Inventory::restore(next_slot, slots);
+
+	Ok((world, state))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::display;
+
+	fn sample_game() -> Game {
+		let mut map = Map::new(8, 12, Tile::Grass);
+		map[(1, 1)] = Tile::Wall;
+		map[(2, 3)] = Tile::Tree;
+		map[(4, 4)] = Tile::DeepWater;
+		map[(5, 6)] = Tile::SnowPeak;
+
+		let mut state = GameState::new(String::from("Pip | the brave"), 1);
+		state.player.row = 3;
+		state.player.col = 7;
+		state.player.hp = 6;
+		state.player.energy = 5;
+		state.turn = 42;
+		state.write_msg_buff("You hit the orc.");
+		state.write_msg_buff("You hit the orc.");
+		state.write_msg_buff("Welcome | back!");
+
+		let mut cutlass = Item::new_weapon("rusty cutlass", 3, 6, '|', display::WHITE);
+		cutlass.equipped = true;
+		let rum = Item::new("draught of rum", ItemType::Drink, 1, true, '!', display::BROWN);
+		let mut gin = Item::new("draught of gin", ItemType::Drink, 1, true, '!', display::WHITE);
+		gin.prev_slot = 'e';
+		state.player.inventory = Inventory::restore('c', vec![('a', cutlass, 1), ('d', rum, 3)]);
+		state.player.inventory.add(gin);
+
+		let mut surface = Level::new(map);
+		let mut orc = Monster::new("orc", 13, 8, 3, 'o', (6, 2), display::BLUE);
+		orc.set_hp(5);
+		orc.set_energy(7);
+		surface.npcs.insert((6, 2), Rc::new(RefCell::new(orc)));
+
+		surface.items.add(0, 0, Item::new("draught of rum", ItemType::Drink, 1, true, '!', display::BROWN));
+		surface.items.add(0, 0, Item::new_weapon("rusty cutlass", 3, 6, '|', display::WHITE));
+		surface.items.add(7, 11, Item::new("draught of gin", ItemType::Drink, 1, true, '!', display::WHITE));
+
+		surface.map.remember(1, 1, Tile::Wall);
+		surface.map.remember(0, 0, Tile::Thing(display::WHITE, '|'));
+
+		let mut cave = Level::new(Map::new(5, 6, Tile::StoneFloor));
+		let rat = Monster::new("rat", 10, 3, 2, 'r', (2, 4), display::BROWN);
+		cave.npcs.insert((2, 4), Rc::new(RefCell::new(rat)));
+		cave.items.add(1, 1, Item::new("draught of rum", ItemType::Drink, 1, true, '!', display::BROWN));
+
+		let mut world = World::new();
+		world.add_level(surface);
+		world.add_level(cave);
+		world.add_stairs((0, 2, 2), (1, 3, 1));
+
+		(world, state)
+	}
+
+	#[test]
+	fn round_trip() {
+		let (world, state) = sample_game();
+		let saved = write_game(&world, &state);
+		let (world2, state2) = read_game(&saved, 1).unwrap();
+
+		assert_eq!(world2.levels.len(), 2);
+		assert_eq!(world2.levels[0].map, world.levels[0].map);
+		assert_eq!(world2.levels[1].map, world.levels[1].map);
+		assert_eq!(world2.get_stairs(), vec![((0, 2, 2), (1, 3, 1))]);
+		assert_eq!(world2.stairs_dest((1, 3, 1)), Some((0, 2, 2)));
+		assert_eq!(state2.level, 0);
+		assert_eq!(state2.player.name, "Pip | the brave");
+		assert_eq!((state2.player.row, state2.player.col), (3, 7));
+		assert_eq!((state2.player.hp, state2.player.ac), (6, 10));
+		assert_eq!(state2.player.energy, 5);
+		assert_eq!(state2.turn, 42);
+		assert_eq!(state2.msg_history, state.msg_history);
+
+		assert_eq!(state2.player.inventory.get_menu(), state.player.inventory.get_menu());
+		assert_eq!(state2.player.inventory.get_next_slot(), 'c');
+		let slots = state2.player.inventory.get_slots();
+		assert_eq!(slots[2].0, 'e');
+		assert_eq!(slots[2].1.prev_slot, 'e');
+		assert_eq!(slots[0].1.dmg, 6);
+		assert!(slots[0].1.equipped);
+
+		let surface = &world2.levels[0];
+		let orc = surface.npcs[&(6, 2)].borrow();
+		assert_eq!(orc.get_name(), "orc");
+		assert_eq!((orc.get_hp(), orc.get_max_hp()), (5, 8));
+		assert_eq!(orc.get_energy(), 7);
+		assert_eq!(orc.get_loc(), (6, 2));
+		assert_eq!(orc.get_tile_info(), (display::BLUE, 'o'));
+
+		assert_eq!(surface.items.get_menu(0, 0), world.levels[0].items.get_menu(0, 0));
+		assert_eq!(surface.items.peek_top(7, 11).name, "draught of gin");
+		assert_eq!(surface.items.count_at(3, 3), 0);
+
+		assert_eq!(surface.map.recall(1, 1), Tile::Wall);
+		assert_eq!(surface.map.recall(0, 0), Tile::Thing(display::WHITE, '|'));
+		assert!(!surface.map.is_explored(5, 5));
+
+		// Each level keeps its own NPCs and items
+		let cave = &world2.levels[1];
+		assert_eq!(cave.npcs.len(), 1);
+		assert_eq!(cave.npcs[&(2, 4)].borrow().get_name(), "rat");
+		assert_eq!(cave.items.peek_top(1, 1).name, "draught of rum");
+		assert_eq!(cave.items.count_at(0, 0), 0);
+
+		// And saving it again gives exactly the same file
+		assert_eq!(write_game(&world2, &state2), saved);
+	}
+
+	#[test]
+	fn player_below_ground() {
+		let (world, mut state) = sample_game();
+		state.level = 1;
+		state.player.row = 3;
+		state.player.col = 1;
+		let (_, state2) = read_game(&write_game(&world, &state), 1).unwrap();
+		assert_eq!((state2.level, state2.player.row, state2.player.col), (1, 3, 1));
+
+		// The cave is too small for where the player was on the surface
+		state.player.row = 6;
+		assert!(read_game(&write_game(&world, &state), 1).is_err());
+	}
+
+	#[test]
+	fn save_and_load_file() {
+		let (world, state) = sample_game();
+		let path = std::env::temp_dir().join(format!("rl-demo-save-test-{}.sav", std::process::id()));
+
+		save_game(&path, &world, &state).unwrap();
+		let loaded = read_game(&fs::read_to_string(&path).unwrap(), 1);
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(loaded.unwrap().1.turn, 42);
+	}
+
+	#[test]
+	fn unsupported_version() {
+		let (world, state) = sample_game();
+		let saved = write_game(&world, &state)
+			.replacen(&format!("save {}", SAVE_VERSION), "save 99", 1);
+
+		match read_game(&saved, 1) {
+			Err(msg) => assert_eq!(msg, format!("The save file is version 99 but this version of the game can only load version {} saves.", SAVE_VERSION)),
+			Ok(_) => panic!("Loaded a save from the future!"),
+		}
+	}
+
+	#[test]
+	fn npcs_and_items_off_the_map() {
+		let (world, state) = sample_game();
+		let saved = write_game(&world, &state);
+
+		// The cave is only 5x6
+		let npc_off = saved.replacen("npc 2|4|", "npc 9|4|", 1);
+		assert!(read_game(&npc_off, 1).err().unwrap().contains("NPC off the map"));
+		let item_off = saved.replacen("item 1|1|", "item 1|6|", 1);
+		assert!(read_game(&item_off, 1).err().unwrap().contains("items off the map"));
+
+		// And an NPC can't share a square with the player
+		let on_player = saved.replacen("npc 6|2|", "npc 3|7|", 1);
+		assert!(read_game(&on_player, 1).is_err());
+	}
+
+	#[test]
+	fn not_a_save_file() {
+		assert!(read_game("", 1).is_err());
+		assert!(read_game("Some notes about orcs", 1).is_err());
+
+		let (world, state) = sample_game();
+		let saved = write_game(&world, &state);
+		let truncated = saved.lines().take(5).collect::<Vec<&str>>().join("\n");
+		assert!(read_game(&truncated, 1).is_err());
+	}
+}
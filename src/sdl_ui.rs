@@ -0,0 +1,386 @@
+extern crate sdl2;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::display::{self, Color, Frontend, BACKSPACE_CH, BLACK, WHITE};
+use crate::map;
+use super::{Cmd, MIN_FOV_HEIGHT, MIN_FOV_WIDTH};
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::EventPump;
+use sdl2::keyboard::Mod;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext};
+use sdl2::ttf::Font;
+
+impl From<Color> for sdl2::pixels::Color {
+	fn from(c: Color) -> sdl2::pixels::Color {
+		sdl2::pixels::Color::RGBA(c.r, c.g, c.b, c.a)
+	}
+}
+
+// I have literally zero clue why Rust wants two lifetime parameters
+// here for the Font ref but this shuts the compiler the hell up...
+pub struct GameUI<'a, 'b> {
+	fov_height: usize,
+	fov_width: usize,
+	screen_width: usize,
+	last_msg: String,
+	screen_width_px: u32,
+	screen_height_px: u32,
+	font_width: u32,
+	font_height: u32,
+	font: &'a Font<'a, 'b>,
+	sm_font_width: u32,
+	sm_font_height: u32,
+	sm_font: &'a Font<'a, 'b>,
+	canvas: WindowCanvas,
+	texture_creator: TextureCreator<WindowContext>,
+	glyphs: HashMap<(char, Color, bool), Texture>,
+	event_pump: EventPump,
+	cursor: Option<(usize, usize)>,
+	pub v_matrix: Vec<Vec<map::Tile>>,
+}
+
+impl<'a, 'b> GameUI<'a, 'b> {
+	pub fn init(font: &'b Font, sm_font: &'b Font,
+			fov_height: usize, fov_width: usize) -> Result<GameUI<'a, 'b>, String> {
+		let (font_width, font_height) = font.size_of_char(' ').unwrap();
+		let (screen_height, screen_width) = display::screen_size(fov_height, fov_width);
+		let screen_width_px = screen_width as u32 * font_width;
+		let screen_height_px = screen_height as u32 * font_height;
+
+		let (sm_font_width, sm_font_height) = sm_font.size_of_char(' ').unwrap();
+
+		let sdl_context = sdl2::init()?;
+		let video_subsystem = sdl_context.video()?;
+		let mut window = video_subsystem.window("RL Demo", screen_width_px, screen_height_px)
+			.position_centered()
+			.resizable()
+			.opengl()
+			.build()
+			.map_err(|e| e.to_string())?;
+		let (min_height, min_width) = display::screen_size(MIN_FOV_HEIGHT, MIN_FOV_WIDTH);
+		window.set_minimum_size(min_width as u32 * font_width, min_height as u32 * font_height)
+			.map_err(|e| e.to_string())?;
+
+		let v_matrix = vec![vec![map::Tile::Blank; fov_width]; fov_height];
+		let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+		let texture_creator = canvas.texture_creator();
+		let gui = GameUI {
+			fov_height, fov_width, screen_width,
+			last_msg: String::from(""),
+			screen_width_px, screen_height_px,
+			font, font_width, font_height,
+			canvas, texture_creator,
+			glyphs: HashMap::new(),
+			event_pump: sdl_context.event_pump().unwrap(),
+			cursor: None,
+			sm_font, sm_font_width, sm_font_height,
+			v_matrix,
+		};
+
+		Ok(gui)
+	}
+
+	// Work out how many squares fit in the window now that it's a new size.
+	// The map viewport gets whatever is left after the message line and
+	// side panel.
+	fn resize(&mut self, width_px: i32, height_px: i32) {
+		self.screen_width_px = width_px as u32;
+		self.screen_height_px = height_px as u32;
+
+		let cols = (self.screen_width_px / self.font_width) as usize;
+		let rows = (self.screen_height_px / self.font_height) as usize;
+		let (fov_height, fov_width) = display::viewport_size(rows, cols);
+		self.fov_height = fov_height;
+		self.fov_width = fov_width;
+		self.screen_width = cols;
+
+		// Some renderers lose their textures when the window changes size,
+		// so start the glyph cache over
+		self.clear_glyphs();
+	}
+
+	// With unsafe_textures on, textures aren't freed when they're dropped so
+	// they have to be destroyed by hand while the canvas is still around
+	fn clear_glyphs(&mut self) {
+		for (_, texture) in self.glyphs.drain() {
+			unsafe { texture.destroy(); }
+		}
+	}
+
+	// Which square of the map viewport (if any) is under the pixel at x, y
+	fn square_at(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+		if x < 0 || y < 0 {
+			return None;
+		}
+
+		let col = x as usize / self.font_width as usize;
+		let row = y as usize / self.font_height as usize;
+		if row < 1 || row > self.fov_height || col >= self.fov_width {
+			None
+		} else {
+			Some((row - 1, col))
+		}
+	}
+
+	fn toggle_fullscreen(&mut self) {
+		let window = self.canvas.window_mut();
+		let next = match window.fullscreen_state() {
+			FullscreenType::Off => FullscreenType::Desktop,
+			_ => FullscreenType::Off,
+		};
+		window.set_fullscreen(next).expect("Error toggling fullscreen!");
+	}
+
+	pub fn pause_for_more(&mut self) {
+		loop {
+			let events: Vec<Event> = self.event_pump.poll_iter().collect();
+			for event in events {
+				// I need to handle a Quit/Exit event here
+				match event {
+					Event::Window { win_event: WindowEvent::SizeChanged(w, h), .. } => {
+						// Redraw what's on the screen at the new size
+						self.resize(w, h);
+						let msg = self.last_msg.clone();
+						self.draw_frame(&msg);
+					},
+					Event::KeyDown {keycode: Some(Keycode::Escape), ..} |
+					Event::KeyDown {keycode: Some(Keycode::Space), ..} => {
+						// It seemed like the ' ' event was still in the queue.
+						// I guess a TextInput event along with the KeyDown event?
+						self.event_pump.poll_event();
+						return;
+					},
+					_ => continue,
+				}
+			}
+		}
+	}
+
+	// Rendering text with TTF and turning it into a texture is slow enough that
+	// doing it for every square on every frame was noticeable, so each glyph is
+	// rendered once the first time it's needed and blitted from then on.
+	fn draw_glyph(&mut self, ch: char, colour: Color, small_font: bool, rect: Rect) {
+		let key = (ch, colour, small_font);
+		if !self.glyphs.contains_key(&key) {
+			let f = if small_font { self.sm_font } else { self.font };
+			let surface = f.render_char(ch)
+				.blended(colour)
+				.expect("Error creating character!");
+			let texture = self.texture_creator.create_texture_from_surface(&surface)
+				.expect("Error creating texture!");
+			self.glyphs.insert(key, texture);
+		}
+
+		let texture = &self.glyphs[&key];
+		self.canvas.copy(texture, None, Some(rect))
+			.expect("Error copying to canvas!");
+	}
+
+	fn write_line(&mut self, row: i32, line: &str, small_font: bool) {
+		let fw: u32;
+		let fh: u32;
+
+		if small_font {
+			fw = self.sm_font_width;
+			fh = self.sm_font_height;
+		} else {
+			fw = self.font_width;
+			fh = self.font_height;
+		}
+
+		if line.is_empty() {
+			self.canvas
+				.fill_rect(Rect::new(0, row * fh as i32, self.screen_width_px, fh))
+				.expect("Error line!");
+
+			return;
+		}
+
+		for (col, ch) in line.chars().enumerate() {
+			if ch == ' ' {
+				continue;
+			}
+			let rect = Rect::new(col as i32 * fw as i32, row * fh as i32, fw, fh);
+			self.draw_glyph(ch, WHITE, small_font, rect);
+		}
+	}
+
+	fn write_sq(&mut self, r: usize, c: usize, tile: map::Tile) {
+		let (ch, char_colour) = display::tile_glyph(tile);
+		if ch == ' ' {
+			return;
+		}
+
+		let rect = Rect::new(c as i32 * self.font_width as i32,
+			(r as i32 + 1) * self.font_height as i32, self.font_width, self.font_height);
+		self.draw_glyph(ch, char_colour, false, rect);
+	}
+
+	fn draw_frame(&mut self, msg: &str) {
+		self.last_msg = String::from(msg);
+		self.canvas.set_draw_color(BLACK);
+		self.canvas.clear();
+
+		self.write_line(0, msg, false);
+		for row in 0..self.v_matrix.len() {
+			for col in 0..self.v_matrix[row].len() {
+				self.write_sq(row, col, self.v_matrix[row][col]);
+			}
+		}
+
+		if let Some((r, c)) = self.cursor {
+			let rect = Rect::new(c as i32 * self.font_width as i32,
+				(r as i32 + 1) * self.font_height as i32, self.font_width, self.font_height);
+			self.canvas.set_draw_color(WHITE);
+			self.canvas.draw_rect(rect).expect("Error drawing cursor!");
+			self.canvas.set_draw_color(BLACK);
+		}
+
+		self.canvas.present();
+	}
+}
+
+impl<'a, 'b> Drop for GameUI<'a, 'b> {
+	fn drop(&mut self) {
+		self.clear_glyphs();
+	}
+}
+
+impl<'a, 'b> Frontend for GameUI<'a, 'b> {
+	// I need to handle quitting the app actions here too
+	fn wait_for_key_input(&mut self) -> Option<char> {
+		loop {
+			let events: Vec<Event> = self.event_pump.poll_iter().collect();
+			for event in events {
+				match event {
+					// Prompts redraw themselves after each key, so all that's
+					// needed here is to note the new layout
+					Event::Window { win_event: WindowEvent::SizeChanged(w, h), .. } => {
+						self.resize(w, h);
+					},
+					Event::TextInput { text:val, .. } => {
+						let ch = val.as_bytes()[0];
+						return Some(ch as char);
+					},
+					Event::KeyDown {keycode: Some(Keycode::Return), .. } => return Some('\n'),
+					Event::KeyDown {keycode: Some(Keycode::Backspace), .. } => return Some(BACKSPACE_CH),
+					Event::KeyDown {keycode: Some(Keycode::Escape), .. } => return None,
+					_ => { continue; }
+				}
+			}
+		}
+	}
+
+	fn get_command(&mut self) -> Cmd {
+		loop {
+			let events: Vec<Event> = self.event_pump.poll_iter().collect();
+			for event in events {
+				match event {
+					Event::KeyDown {keycode: Some(Keycode::Escape), ..}
+						| Event::Quit {..} => { return Cmd::Exit },
+					Event::Window { win_event: WindowEvent::SizeChanged(w, h), .. } => {
+						self.resize(w, h);
+						return Cmd::Redraw;
+					},
+					Event::KeyDown {keycode: Some(Keycode::F11), .. } => {
+						self.toggle_fullscreen();
+					},
+					Event::KeyDown {keycode: Some(Keycode::H), keymod: Mod::LCTRLMOD, .. } |
+					Event::KeyDown {keycode: Some(Keycode::H), keymod: Mod::RCTRLMOD, .. } => {
+						return Cmd::MsgHistory;
+					},
+					Event::TextInput { text:val, .. } => {
+						if let Some(cmd) = val.chars().next().and_then(display::key_to_cmd) {
+							return cmd;
+						}
+					},
+					Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+						// Clicking a square on the map means travel there
+						if let Some(sq) = self.square_at(x, y) {
+							return Cmd::TravelTo(sq.0, sq.1);
+						}
+					},
+					_ => { continue },
+				}
+			}
+    	}
+	}
+
+	fn viewport_size(&self) -> (usize, usize) {
+		(self.fov_height, self.fov_width)
+	}
+
+	fn set_v_matrix(&mut self, v_matrix: Vec<Vec<map::Tile>>) {
+		self.v_matrix = v_matrix;
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		self.cursor = cursor;
+	}
+
+	// What I should do here but am not is make sure each line will fit on the
+	// screen without being cut off. For the moment, I just gotta make sure any
+	// lines don't have too many characterse. Something for a post 7DRL world
+	// I guess.
+	fn write_long_msg(&mut self, lines: &[String], small_text: bool) {
+		self.canvas.clear();
+
+		let display_lines = (self.screen_height_px / self.sm_font_height) as usize;
+		let line_count = lines.len();
+		let mut curr_line = 0;
+		let mut curr_row = 0;
+		while curr_line < line_count {
+			self.write_line(curr_row as i32, &lines[curr_line], small_text);
+			curr_line += 1;
+			curr_row += 1;
+
+			if curr_row == display_lines - 2 && curr_line < line_count {
+				self.write_line(curr_row as i32, "", small_text);
+				self.write_line(curr_row as i32 + 1, "-- Press space to continue --", small_text);
+				self.canvas.present();
+				self.pause_for_more();
+				curr_row = 0;
+				self.canvas.clear();
+			}
+		}
+
+		self.write_line(curr_row as i32, "", small_text);
+		self.write_line(curr_row as i32 + 1, "-- Press space to continue --", small_text);
+		self.canvas.present();
+		self.pause_for_more();
+	}
+
+	fn write_screen(&mut self, msgs: &mut VecDeque<String>) {
+		let lines = display::paginate_msgs(msgs, self.screen_width);
+		let last = lines.len() - 1;
+		for (j, line) in lines.iter().enumerate() {
+			self.draw_frame(line);
+			if j < last {
+				self.pause_for_more();
+			}
+		}
+	}
+
+	fn draw_menu(&mut self, menu: &[String], answers: &HashSet<u8>) {
+		self.canvas.clear();
+		for (j, line) in menu.iter().enumerate() {
+			if j > 0 && answers.contains(&(j as u8 - 1)) {
+				let mut s = String::from("\u{2713} ");
+				s.push_str(line);
+				self.write_line(j as i32, &s, false);
+			} else {
+				self.write_line(j as i32, line, false);
+			}
+		}
+
+		self.write_line(menu.len() as i32 + 1, "", false);
+		self.write_line(menu.len() as i32 + 2, "Select one or more options, then hit Return.", false);
+		self.canvas.present();
+	}
+}
@@ -0,0 +1,327 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use crate::display::{self, Color, Frontend, BACKSPACE_CH, ESC_CH, WHITE};
+use crate::map;
+use super::Cmd;
+
+const CTRL_C_CH: char = '\u{0003}';
+const DEL_CH: char = '\u{007f}';
+
+// Run stty against the controlling terminal. I'm shelling out instead of
+// pulling in a crate for termios since all I need is raw mode on and off.
+fn stty(args: &[&str]) -> Result<String, String> {
+	let output = Command::new("stty")
+		.args(args)
+		.stdin(Stdio::inherit())
+		.output()
+		.map_err(|e| format!("Error running stty: {}", e))?;
+
+	if !output.status.success() {
+		return Err(String::from("stty failed. Is stdin a terminal?"));
+	}
+
+	Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// What came in from the keyboard. Arrow keys, Home, F1 and so on don't send
+// a character but an escape sequence (ESC [ A for up, say).
+#[derive(Debug, PartialEq)]
+enum Key {
+	Ch(char),
+	Arrow(Cmd),
+	Other,
+}
+
+// Turn the bytes that followed an ESC into a key. No bytes at all means
+// Esc itself was pressed.
+fn escape_seq_to_key(seq: &[u8]) -> Key {
+	if seq.is_empty() {
+		return Key::Ch(ESC_CH);
+	}
+
+	// Arrows are ESC [ A or, in application mode, ESC O A
+	if seq.len() == 2 && (seq[0] == b'[' || seq[0] == b'O') {
+		match seq[1] {
+			b'A' => return Key::Arrow(Cmd::MoveN),
+			b'B' => return Key::Arrow(Cmd::MoveS),
+			b'C' => return Key::Arrow(Cmd::MoveE),
+			b'D' => return Key::Arrow(Cmd::MoveW),
+			_ => { },
+		}
+	}
+
+	Key::Other
+}
+
+fn fg_colour(c: Color) -> String {
+	format!("\x1b[38;2;{};{};{}m", c.r, c.g, c.b)
+}
+
+// A frontend that draws with ANSI escape codes so the game can be played
+// in a plain terminal (say, over ssh) instead of the SDL window.
+pub struct TerminalUI {
+	saved_stty: String,
+	fov_height: usize,
+	fov_width: usize,
+	screen_height: usize,
+	screen_width: usize,
+	cursor: Option<(usize, usize)>,
+	pub v_matrix: Vec<Vec<map::Tile>>,
+}
+
+impl TerminalUI {
+	pub fn init(fov_height: usize, fov_width: usize) -> Result<TerminalUI, String> {
+		let saved_stty = stty(&["-g"])?;
+		stty(&["raw", "-echo"])?;
+
+		let (screen_height, screen_width) = display::screen_size(fov_height, fov_width);
+		let ui = TerminalUI {
+			saved_stty,
+			fov_height, fov_width, screen_height, screen_width,
+			cursor: None,
+			v_matrix: vec![vec![map::Tile::Blank; fov_width]; fov_height],
+		};
+
+		// Switch to the alternate screen and hide the cursor
+		ui.output("\x1b[?1049h\x1b[?25l");
+
+		Ok(ui)
+	}
+
+	fn output(&self, s: &str) {
+		let mut out = io::stdout();
+		out.write_all(s.as_bytes()).expect("Error writing to terminal!");
+		out.flush().expect("Error writing to terminal!");
+	}
+
+	fn read_key(&mut self) -> Key {
+		let mut stdin = io::stdin();
+		let mut buf = [0u8; 4];
+		stdin.read_exact(&mut buf[..1]).expect("Error reading from terminal!");
+
+		if buf[0] == ESC_CH as u8 {
+			return escape_seq_to_key(&self.read_escape_seq());
+		}
+
+		// Multibyte UTF-8 sequences tell us their length in the first byte
+		let len = if buf[0] >= 0xf0 {
+			4
+		} else if buf[0] >= 0xe0 {
+			3
+		} else if buf[0] >= 0xc0 {
+			2
+		} else {
+			1
+		};
+		if len > 1 {
+			stdin.read_exact(&mut buf[1..len]).expect("Error reading from terminal!");
+		}
+
+		match std::str::from_utf8(&buf[..len]) {
+			Ok(s) => Key::Ch(s.chars().next().unwrap()),
+			Err(_) => Key::Ch('\0'),
+		}
+	}
+
+	// Read whatever is left of an escape sequence after the ESC. The bytes of
+	// a sequence all arrive together, so I switch the terminal to time out
+	// after a tenth of a second and if nothing else shows up it was a lone
+	// Esc. Otherwise, read up to the end of the sequence so none of it is
+	// left over to be taken as keypresses.
+	fn read_escape_seq(&mut self) -> Vec<u8> {
+		let mut stdin = io::stdin();
+		let mut seq = Vec::new();
+		let mut buf = [0u8; 1];
+
+		let _ = stty(&["min", "0", "time", "1"]);
+		while let Ok(1) = stdin.read(&mut buf) {
+			seq.push(buf[0]);
+
+			// ESC [ sequences end with a byte from @ to ~ and everything else
+			// (ESC O A, or Alt plus a key) is two bytes long
+			let done = match seq[0] {
+				b'[' => seq.len() > 1 && (0x40..=0x7e).contains(&buf[0]),
+				b'O' => seq.len() == 2,
+				_ => true,
+			};
+			if done {
+				break;
+			}
+		}
+		let _ = stty(&["min", "1", "time", "0"]);
+
+		seq
+	}
+
+	// Keys that don't send a character aren't any use when typing
+	fn read_char(&mut self) -> char {
+		loop {
+			if let Key::Ch(ch) = self.read_key() {
+				return ch;
+			}
+		}
+	}
+
+	fn pause_for_more(&mut self) {
+		loop {
+			match self.read_char() {
+				' ' | ESC_CH | CTRL_C_CH => return,
+				_ => continue,
+			}
+		}
+	}
+
+	// Raw mode means I have to do my own carriage returns
+	fn write_lines(&self, lines: &Vec<String>) {
+		let mut s = String::from("\x1b[H\x1b[0m");
+		for line in lines {
+			s.push_str(line);
+			s.push_str("\x1b[0m\x1b[K\r\n");
+		}
+		s.push_str("\x1b[J");
+
+		self.output(&s);
+	}
+
+	fn draw_frame(&mut self, msg: &str) {
+		let mut lines = vec![format!("{}{}", fg_colour(WHITE), msg)];
+		for row in 0..self.v_matrix.len() {
+			let mut s = String::from("");
+			let mut curr_colour = None;
+			for col in 0..self.v_matrix[row].len() {
+				let (ch, colour) = display::tile_glyph(self.v_matrix[row][col]);
+				if curr_colour != Some(colour) {
+					s.push_str(&fg_colour(colour));
+					curr_colour = Some(colour);
+				}
+				// Combining characters (like the grass glyph) would otherwise
+				// glom onto the previous square
+				if ('\u{0300}'..='\u{036f}').contains(&ch) {
+					s.push(' ');
+				}
+				if self.cursor == Some((row, col)) {
+					// Reverse video for the cursor square
+					s.push_str("\x1b[7m");
+					s.push(ch);
+					s.push_str("\x1b[27m");
+				} else {
+					s.push(ch);
+				}
+			}
+			lines.push(s);
+		}
+
+		self.write_lines(&lines);
+	}
+}
+
+impl Drop for TerminalUI {
+	fn drop(&mut self) {
+		self.output("\x1b[0m\x1b[?25h\x1b[?1049l");
+		let _ = stty(&[&self.saved_stty]);
+	}
+}
+
+impl Frontend for TerminalUI {
+	fn wait_for_key_input(&mut self) -> Option<char> {
+		match self.read_char() {
+			'\r' | '\n' => Some('\n'),
+			DEL_CH | BACKSPACE_CH => Some(BACKSPACE_CH),
+			ESC_CH | CTRL_C_CH => None,
+			ch => Some(ch),
+		}
+	}
+
+	fn get_command(&mut self) -> Cmd {
+		loop {
+			let ch = match self.read_key() {
+				Key::Ch(ch) => ch,
+				Key::Arrow(cmd) => return cmd,
+				Key::Other => continue,
+			};
+			if ch == CTRL_C_CH {
+				return Cmd::Exit;
+			}
+
+			if let Some(cmd) = display::key_to_cmd(ch) {
+				return cmd;
+			}
+		}
+	}
+
+	fn viewport_size(&self) -> (usize, usize) {
+		(self.fov_height, self.fov_width)
+	}
+
+	fn set_v_matrix(&mut self, v_matrix: Vec<Vec<map::Tile>>) {
+		self.v_matrix = v_matrix;
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		self.cursor = cursor;
+	}
+
+	fn write_long_msg(&mut self, lines: &[String], _small_text: bool) {
+		let display_lines = self.screen_height;
+		let mut page = Vec::new();
+		for j in 0..lines.len() {
+			page.push(lines[j].clone());
+
+			if page.len() == display_lines - 2 && j < lines.len() - 1 {
+				page.push(String::from(""));
+				page.push(String::from("-- Press space to continue --"));
+				self.write_lines(&page);
+				self.pause_for_more();
+				page.clear();
+			}
+		}
+
+		page.push(String::from(""));
+		page.push(String::from("-- Press space to continue --"));
+		self.write_lines(&page);
+		self.pause_for_more();
+	}
+
+	fn write_screen(&mut self, msgs: &mut VecDeque<String>) {
+		let lines = display::paginate_msgs(msgs, self.screen_width);
+		let last = lines.len() - 1;
+		for (j, line) in lines.iter().enumerate() {
+			self.draw_frame(line);
+			if j < last {
+				self.pause_for_more();
+			}
+		}
+	}
+
+	fn draw_menu(&mut self, menu: &[String], answers: &HashSet<u8>) {
+		let mut lines = Vec::new();
+		for (j, line) in menu.iter().enumerate() {
+			if j > 0 && answers.contains(&(j as u8 - 1)) {
+				lines.push(format!("\u{2713} {}", line));
+			} else {
+				lines.push(line.clone());
+			}
+		}
+		lines.push(String::from(""));
+		lines.push(String::from("Select one or more options, then hit Return."));
+
+		self.write_lines(&lines);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn escape_sequences() {
+		assert_eq!(escape_seq_to_key(b""), Key::Ch(ESC_CH));
+		assert_eq!(escape_seq_to_key(b"[A"), Key::Arrow(Cmd::MoveN));
+		assert_eq!(escape_seq_to_key(b"OD"), Key::Arrow(Cmd::MoveW));
+		assert_eq!(escape_seq_to_key(b"[1~"), Key::Other);
+		assert_eq!(escape_seq_to_key(b"[1;5C"), Key::Other);
+		assert_eq!(escape_seq_to_key(b"x"), Key::Other);
+	}
+}
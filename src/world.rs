@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use crate::items::ItemsTable;
+use crate::map::Tile;
+use super::{Map, NPCTable};
+
+// A square somewhere in the world: (level, row, col)
+pub type WorldLoc = (usize, usize, usize);
+
+// One level of the world and everything on it. Only the level the player is
+// on gets to do anything; the rest just wait, exactly as they were left,
+// until the player comes back.
+pub struct Level {
+	pub map: Map,
+	pub npcs: NPCTable,
+	pub items: ItemsTable,
+}
+
+impl Level {
+	pub fn new(map: Map) -> Level {
+		Level { map, npcs: HashMap::new(), items: ItemsTable::new() }
+	}
+}
+
+// All the levels of the game (level 0 is the island's surface) and the
+// stairs joining them together.
+pub struct World {
+	pub levels: Vec<Level>,
+	stairs: HashMap<WorldLoc, WorldLoc>,
+}
+
+impl World {
+	pub fn new() -> World {
+		World { levels: Vec::new(), stairs: HashMap::new() }
+	}
+
+	// Returns the new level's number
+	pub fn add_level(&mut self, level: Level) -> usize {
+		self.levels.push(level);
+		self.levels.len() - 1
+	}
+
+	// Join two squares with a staircase, going down from top and coming back
+	// up at bottom
+	pub fn add_stairs(&mut self, top: WorldLoc, bottom: WorldLoc) {
+		self.levels[top.0].map[(top.1, top.2)] = Tile::StairsDown;
+		self.levels[bottom.0].map[(bottom.1, bottom.2)] = Tile::StairsUp;
+		self.stairs.insert(top, bottom);
+		self.stairs.insert(bottom, top);
+	}
+
+	// Where the stairs at loc come out, if there are any
+	pub fn stairs_dest(&self, loc: WorldLoc) -> Option<WorldLoc> {
+		self.stairs.get(&loc).copied()
+	}
+
+	// Every staircase as a (top, bottom) pair, in order
+	pub fn get_stairs(&self) -> Vec<(WorldLoc, WorldLoc)> {
+		let mut stairs = self.stairs.iter()
+			.filter(|(top, _)| self.levels[top.0].map[(top.1, top.2)] == Tile::StairsDown)
+			.map(|(top, bottom)| (*top, *bottom))
+			.collect::<Vec<(WorldLoc, WorldLoc)>>();
+		stairs.sort();
+
+		stairs
+	}
+}